@@ -0,0 +1,68 @@
+use crate::energy::{EnergySource, measure_power_sample};
+use crate::monitor::PowerMonitor;
+use crate::power::PowerReading;
+use crate::topology::CpuTopology;
+use crate::{AVERAGING_ITERATIONS, detect_cpu_type};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio_stream::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Streams averaged readings of the last `AVERAGING_ITERATIONS` samples, one per sample of
+/// `interval`. MSR reads and the wait between snapshots block, so every sample is measured on
+/// tokio's blocking pool. The stream ends after yielding the first error, and sampling stops once
+/// the stream is dropped.
+///
+/// Must be called from within a tokio runtime.
+pub async fn stream_power_readings(
+	topology: CpuTopology,
+	interval: Duration,
+) -> impl Stream<Item = io::Result<PowerReading>> {
+	let (sender, receiver) = mpsc::channel(1);
+	tokio::spawn(async move {
+		if let Err(e) = sample_readings(&sender, topology, interval).await {
+			let _ = sender.send(Err(e)).await;
+		}
+	});
+	ReceiverStream::new(receiver)
+}
+
+fn join_error(e: task::JoinError) -> io::Error {
+	io::Error::other(format!("Power sampling task failed: {e}"))
+}
+
+async fn sample_readings(
+	sender: &mpsc::Sender<io::Result<PowerReading>>,
+	topology: CpuTopology,
+	interval: Duration,
+) -> io::Result<()> {
+	let physical_cores = topology.physical_cores();
+	let (cpu_type, source, mut monitor) = task::spawn_blocking(move || {
+		let cpu_type = detect_cpu_type();
+		let source = EnergySource::detect(&cpu_type)?;
+		let mut monitor = PowerMonitor::new(physical_cores, AVERAGING_ITERATIONS);
+		monitor.calibrate(&cpu_type, &source)?;
+		Ok::<_, io::Error>((cpu_type, source, monitor))
+	})
+	.await
+	.map_err(join_error)??;
+
+	let topology = Arc::new(topology);
+	let source = Arc::new(source);
+	while !sender.is_closed() {
+		let (topology, source) = (Arc::clone(&topology), Arc::clone(&source));
+		let sample = task::spawn_blocking(move || measure_power_sample(&cpu_type, &topology, &source, interval))
+			.await
+			.map_err(join_error)??;
+		monitor.update_readings(&sample)?;
+		if let Some(reading) = monitor.peek_latest_reading() {
+			if sender.send(Ok(reading)).await.is_err() {
+				break;
+			}
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,95 @@
+use crate::energy::{EnergySnapshot, read_energy_snapshot};
+use crate::info::escape_json;
+use crate::topology::CpuTopology;
+use crate::{CpuType, get_energy_counter_mask, get_energy_unit};
+use std::process::{Command, ExitStatus};
+use std::time::Duration;
+use std::{fmt, io, thread};
+
+/// How often the child is checked for having exited
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Energy is accumulated at least this often, well before the 32-bit package counter can wrap
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Package energy used while a command ran.
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+	pub command: String,
+	pub status: ExitStatus,
+	pub duration: Duration,
+	pub energy_joules: f64,
+}
+
+impl BenchResult {
+	#[must_use]
+	pub fn avg_watts(&self) -> f64 {
+		let duration_s = self.duration.as_secs_f64();
+		if duration_s > 0.0 {
+			self.energy_joules / duration_s
+		} else {
+			0.0
+		}
+	}
+
+	/// Formats the result as `{"command":"make -j8","duration_ms":1234,"energy_joules":56.78,
+	/// "avg_watts":46.01}`.
+	#[must_use]
+	pub fn to_json(&self) -> String {
+		format!(
+			"{{\"command\":{},\"duration_ms\":{},\"energy_joules\":{:.3},\"avg_watts\":{:.3}}}",
+			escape_json(&self.command),
+			self.duration.as_millis(),
+			self.energy_joules,
+			self.avg_watts()
+		)
+	}
+}
+
+impl fmt::Display for BenchResult {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "Command:       {}", self.command)?;
+		writeln!(f, "Duration:      {:.3} s", self.duration.as_secs_f64())?;
+		writeln!(f, "Energy:        {:.2} J", self.energy_joules)?;
+		write!(f, "Average power: {:.2} W", self.avg_watts())
+	}
+}
+
+/// Runs `argv` to completion and measures the package energy from just before it is spawned to
+/// just after it exits. Unlike timing a command, this includes the power drawn while it waits on
+/// I/O. Power of everything else running on the CPU is included too, so benchmark on an otherwise
+/// idle system.
+pub fn bench_command(cpu_type: &CpuType, topology: &CpuTopology, argv: &[String]) -> io::Result<BenchResult> {
+	let Some((program, args)) = argv.split_first() else {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "No command to benchmark"));
+	};
+	let energy_unit = get_energy_unit(cpu_type)?;
+	let counter_mask = get_energy_counter_mask(cpu_type);
+	let mut energy_joules = 0.0;
+	let mut accumulate = |from: &EnergySnapshot, to: &EnergySnapshot| {
+		energy_joules += to.diff(from, counter_mask).package as f64 / (1u64 << energy_unit) as f64;
+	};
+
+	let mut checkpoint = read_energy_snapshot(cpu_type, topology)?;
+	let started_at = checkpoint.captured_at;
+	let mut child = Command::new(program).args(args).spawn()?;
+	let status = loop {
+		if let Some(status) = child.try_wait()? {
+			break status;
+		}
+		thread::sleep(CHILD_POLL_INTERVAL);
+		if checkpoint.captured_at.elapsed() >= CHECKPOINT_INTERVAL {
+			let snapshot = read_energy_snapshot(cpu_type, topology)?;
+			accumulate(&checkpoint, &snapshot);
+			checkpoint = snapshot;
+		}
+	};
+	let finished = read_energy_snapshot(cpu_type, topology)?;
+	accumulate(&checkpoint, &finished);
+
+	Ok(BenchResult {
+		command: argv.join(" "),
+		status,
+		duration: finished.captured_at.saturating_duration_since(started_at),
+		energy_joules,
+	})
+}
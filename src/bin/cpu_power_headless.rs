@@ -0,0 +1,62 @@
+use cpu_power::energy::{EnergySource, measure_power_sample};
+use cpu_power::monitor::PowerMonitor;
+use cpu_power::signal::install_shutdown_handler;
+use cpu_power::topology::CpuTopology;
+use cpu_power::{
+	AVERAGING_ITERATIONS, CpuType, DATA_COLLECTION_INTERVAL_MS, DISPLAY_UPDATE_INTERVAL_MS, detect_cpu_type, display,
+};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+fn main() -> io::Result<()> {
+	let cpu_type = detect_cpu_type();
+	if let CpuType::Unsupported = cpu_type {
+		eprintln!("Unsupported CPU type or unable to detect CPU type.");
+		std::process::exit(1);
+	}
+
+	let source = EnergySource::detect(&cpu_type)?;
+	if let Some(note) = source.note() {
+		eprintln!("{note}");
+	}
+	let topology = CpuTopology::detect();
+	let physical_cores = topology.physical_cores();
+	let mut monitor = PowerMonitor::new(physical_cores, AVERAGING_ITERATIONS);
+	monitor.calibrate(&cpu_type, &source)?;
+	let mut stdout = io::stdout().lock();
+	let display_interval = Duration::from_millis(DISPLAY_UPDATE_INTERVAL_MS);
+	let mut last_display_time = Instant::now();
+	let shutdown = install_shutdown_handler()?;
+
+	while !shutdown.load(Ordering::SeqCst) {
+		let interval = Duration::from_millis(DATA_COLLECTION_INTERVAL_MS);
+		let sample = measure_power_sample(&cpu_type, &topology, &source, interval)?;
+		monitor.update_readings(&sample)?;
+
+		if last_display_time.elapsed() >= display_interval {
+			let Some(reading) = monitor.peek_latest_reading() else {
+				continue;
+			};
+
+			let mut line = format!("package={:.2}", reading.package);
+			for (core_id, power) in reading.cores.iter().enumerate() {
+				if let Some(watts) = power {
+					let _ = write!(line, " core{core_id}={watts:.2}");
+				}
+			}
+			writeln!(stdout, "{line}")?;
+			stdout.flush()?;
+			last_display_time = Instant::now();
+		}
+	}
+	// Keep stdout to the reading lines
+	let summary = monitor.session_summary();
+	eprintln!("{}", display::format_session_summary(&summary));
+	eprintln!(
+		"{}",
+		display::format_session_energy(summary.total_energy_joules, summary.elapsed)
+	);
+	Ok(())
+}
@@ -0,0 +1,21 @@
+use cpu_power::info::detect_tdp;
+use cpu_power::tui::monitor_with_dashboard;
+use cpu_power::{CpuType, MonitorConfig, detect_cpu_type, display};
+use std::io;
+
+fn main() -> io::Result<()> {
+	let cpu_type = detect_cpu_type();
+	if let CpuType::Unsupported = cpu_type {
+		eprintln!("Unsupported CPU type or unable to detect CPU type.");
+		std::process::exit(1);
+	}
+
+	let tdp_watts = detect_tdp(&cpu_type, None).map(|tdp| tdp.watts());
+	let summary = monitor_with_dashboard(&cpu_type, &MonitorConfig::default(), tdp_watts, |_| {})?;
+	println!("{}", display::format_session_summary(&summary));
+	println!(
+		"{}",
+		display::format_session_energy(summary.total_energy_joules, summary.elapsed)
+	);
+	Ok(())
+}
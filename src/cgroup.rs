@@ -0,0 +1,99 @@
+use crate::power::PowerReading;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const PROC_STAT_PATH: &str = "/proc/stat";
+
+/// CPU time of a cgroup v2 from its `cpu.stat`, next to the busy CPU time of the whole system
+/// from `/proc/stat`, all in µs. Both grow from boot, so compare two reads with [`Self::since`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CgroupStats {
+	pub usage_usec: u64,
+	pub user_usec: u64,
+	pub system_usec: u64,
+	/// Non-idle CPU time of every CPU of the system
+	pub system_busy_usec: u64,
+}
+
+impl CgroupStats {
+	/// Reads the cgroup at `path`, which is taken as relative to `/sys/fs/cgroup` unless it is
+	/// absolute.
+	pub fn read(path: &Path) -> io::Result<Self> {
+		let path = cgroup_path(path);
+		let cpu_stat = fs::read_to_string(path.join("cpu.stat")).map_err(|e| {
+			io::Error::new(
+				e.kind(),
+				format!("Cannot read cpu.stat of cgroup {}: {e}", path.display()),
+			)
+		})?;
+		let field = |name: &str| {
+			cpu_stat
+				.lines()
+				.find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.trim().parse().ok())
+				.unwrap_or(0)
+		};
+		Ok(Self {
+			usage_usec: field("usage_usec"),
+			user_usec: field("user_usec"),
+			system_usec: field("system_usec"),
+			system_busy_usec: read_system_busy_usec()?,
+		})
+	}
+
+	/// CPU time spent between `earlier` and this read.
+	#[must_use]
+	pub const fn since(&self, earlier: &Self) -> Self {
+		Self {
+			usage_usec: self.usage_usec.saturating_sub(earlier.usage_usec),
+			user_usec: self.user_usec.saturating_sub(earlier.user_usec),
+			system_usec: self.system_usec.saturating_sub(earlier.system_usec),
+			system_busy_usec: self.system_busy_usec.saturating_sub(earlier.system_busy_usec),
+		}
+	}
+
+	/// Fraction of the system's busy CPU time used by the cgroup, from 0 to 1.
+	#[must_use]
+	pub fn cpu_share(&self) -> f64 {
+		if self.system_busy_usec == 0 {
+			return 0.0;
+		}
+		(self.usage_usec as f64 / self.system_busy_usec as f64).clamp(0.0, 1.0)
+	}
+}
+
+/// Package power attributed to a cgroup by its share of the busy CPU time, with `cgroup` the
+/// difference of two reads over about the span of `global`. Idle and uncore power is split the
+/// same way, so this is an estimate for comparing workloads rather than a measurement.
+#[must_use]
+pub fn estimate_cgroup_power(cgroup: &CgroupStats, global: &PowerReading) -> f64 {
+	global.package * cgroup.cpu_share()
+}
+
+fn cgroup_path(path: &Path) -> PathBuf {
+	if path.is_absolute() {
+		path.to_path_buf()
+	} else {
+		Path::new(CGROUP_ROOT).join(path)
+	}
+}
+
+/// Sums the non-idle fields of the aggregate `cpu` line of `/proc/stat`: user, nice, system,
+/// irq, softirq and steal. Guest time is already part of user time.
+fn read_system_busy_usec() -> io::Result<u64> {
+	let stat = fs::read_to_string(PROC_STAT_PATH)?;
+	let fields: Vec<u64> = stat
+		.lines()
+		.find_map(|line| line.strip_prefix("cpu "))
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No cpu line in /proc/stat"))?
+		.split_whitespace()
+		.map(|field| field.parse().unwrap_or(0))
+		.collect();
+	let busy_ticks: u64 = [0, 1, 2, 5, 6, 7].iter().filter_map(|&index| fields.get(index)).sum();
+	// SAFETY: sysconf has no preconditions
+	let ticks_per_second = u64::try_from(unsafe { libc::sysconf(libc::_SC_CLK_TCK) })
+		.ok()
+		.filter(|&ticks| ticks > 0)
+		.unwrap_or(100);
+	Ok(busy_ticks * 1_000_000 / ticks_per_second)
+}
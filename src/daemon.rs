@@ -0,0 +1,122 @@
+use crate::display::write_json_reading;
+use crate::power::PowerReading;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use std::{fs, thread};
+
+pub const DEFAULT_SOCKET_PATH: &str = "/run/cpu-power/monitor.sock";
+/// Readings queued for a client that stopped reading before newer ones are dropped for it
+const CLIENT_QUEUE_LENGTH: usize = 64;
+/// How long `query_reading` waits for the daemon to send a reading
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a write may block on a client that stopped reading before it is disconnected
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type ClientList = Arc<Mutex<Vec<SyncSender<Arc<[u8]>>>>>;
+
+/// Streams readings as JSON lines, in the format of [`write_json_reading`], to every client
+/// connected to a Unix socket. Only the daemon reads the MSRs, however many clients there are.
+/// The socket is removed when the server is dropped.
+pub struct DaemonServer {
+	path: PathBuf,
+	clients: ClientList,
+}
+
+impl DaemonServer {
+	/// Listens on `path`, creating its directory and replacing a socket left behind by a daemon
+	/// that did not exit cleanly. Fails if another daemon still answers on it. The socket is
+	/// world-writable so unprivileged users can query a daemon running as root.
+	pub fn bind(path: &Path) -> io::Result<Self> {
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+		if path.exists() {
+			if UnixStream::connect(path).is_ok() {
+				return Err(io::Error::new(
+					io::ErrorKind::AddrInUse,
+					format!("A daemon is already listening on {}", path.display()),
+				));
+			}
+			fs::remove_file(path)?;
+		}
+		let listener = UnixListener::bind(path)?;
+		fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+
+		let clients = ClientList::default();
+		let accepted = Arc::clone(&clients);
+		thread::spawn(move || {
+			for stream in listener.incoming().flatten() {
+				let (sender, receiver) = mpsc::sync_channel(CLIENT_QUEUE_LENGTH);
+				accepted.lock().unwrap_or_else(PoisonError::into_inner).push(sender);
+				thread::spawn(move || serve_client(stream, &receiver));
+			}
+		});
+		Ok(Self {
+			path: path.to_path_buf(),
+			clients,
+		})
+	}
+
+	#[must_use]
+	pub fn client_count(&self) -> usize {
+		self.clients.lock().unwrap_or_else(PoisonError::into_inner).len()
+	}
+
+	/// Queues `reading` for every client and forgets those that disconnected. A client whose
+	/// queue is full misses the reading instead of holding up the others.
+	pub fn broadcast(&self, reading: &PowerReading) -> io::Result<()> {
+		let mut line = Vec::new();
+		write_json_reading(&mut line, reading)?;
+		let line: Arc<[u8]> = line.into();
+		self.clients
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.retain(|client| !matches!(client.try_send(Arc::clone(&line)), Err(TrySendError::Disconnected(_))));
+		Ok(())
+	}
+}
+
+impl Drop for DaemonServer {
+	fn drop(&mut self) {
+		let _ = fs::remove_file(&self.path);
+	}
+}
+
+/// Writes the queued lines to one client until it disconnects, stops reading for longer than the
+/// write timeout or the server is dropped. Returning drops the receiver, so the next broadcast
+/// forgets the client.
+fn serve_client(mut stream: UnixStream, lines: &Receiver<Arc<[u8]>>) {
+	if stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT)).is_err() {
+		return;
+	}
+	for line in lines {
+		if stream.write_all(&line).is_err() {
+			return;
+		}
+	}
+}
+
+/// Connects to the daemon on `path` and returns the next reading it sends, as a JSON line
+/// without the newline.
+pub fn query_reading(path: &Path) -> io::Result<String> {
+	let stream = UnixStream::connect(path).map_err(|e| {
+		io::Error::new(
+			e.kind(),
+			format!("Cannot connect to the daemon on {}: {e}", path.display()),
+		)
+	})?;
+	stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+	let mut line = String::new();
+	if BufReader::new(stream).read_line(&mut line)? == 0 {
+		return Err(io::Error::new(
+			io::ErrorKind::UnexpectedEof,
+			"The daemon closed the connection before sending a reading",
+		));
+	}
+	Ok(line.trim_end().to_string())
+}
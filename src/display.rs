@@ -0,0 +1,881 @@
+use crate::info::{RaplLimits, escape_json};
+use crate::monitor::{PowerExtremes, SessionSummary};
+use crate::power::{EnergyBudget, PowerReading};
+use crate::stats::format_rfc3339;
+use crate::topology::CpuTopology;
+use crate::{CoreType, HwpCapabilities, MonitorConfig};
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant, SystemTime};
+
+const STABLE_REFRESHES_BEFORE_SLOWDOWN: u32 = 5;
+const MAX_ADAPTIVE_REFRESH_MS: u64 = 1000;
+/// Fraction of PL1 from which package power is shown in yellow, and in red from PL1 on
+const PL1_WARNING_RATIO: f64 = 0.8;
+/// Fractions of their share of the TDP from which core powers turn from green to yellow, and
+/// above which they turn red
+const TDP_YELLOW_RATIO: f64 = 0.5;
+const TDP_RED_RATIO: f64 = 0.8;
+const ANSI_GREEN: &str = "\x1B[32m";
+const ANSI_YELLOW: &str = "\x1B[33m";
+const ANSI_RED: &str = "\x1B[31m";
+const ANSI_RESET: &str = "\x1B[0m";
+/// Width of the widest core cell, `Core 127: 12.34 W @ 4500 MHz (100°C) [★]`, and the ` | `
+/// before the next one, so cells never wrap
+pub const CORE_COLUMN_WIDTH: usize = 44;
+/// Columns assumed when stdout is not a terminal
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+/// The package sparkline keeps the readings of this fraction of the terminal width, and shows as
+/// many of them as fit after the figures of the package line
+const SPARKLINE_WIDTH_DIVISOR: usize = 4;
+/// Columns below which the package line leaves out the sparkline
+const MIN_SPARKLINE_WIDTH: usize = 8;
+
+/// How readings are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+	/// In-place ANSI display
+	#[default]
+	Terminal,
+	/// One JSON object per line and measurement
+	Json,
+	/// One line of `key=value` pairs per measurement, without escape codes, for pipes and files
+	Plain,
+	/// One short line per display interval for status bars, as JSON if `json`
+	Compact { json: bool },
+}
+
+impl OutputMode {
+	/// JSON if asked for, otherwise the ANSI display when stdout is a terminal and plain lines
+	/// when it is piped or redirected.
+	#[must_use]
+	pub fn detect(json: bool) -> Self {
+		if json {
+			Self::Json
+		} else if io::stdout().is_terminal() {
+			Self::Terminal
+		} else {
+			Self::Plain
+		}
+	}
+}
+
+/// Destination of the rendered display. Any writer works: the terminal gets `io::stdout()`, while
+/// a `Vec<u8>` captures the output, escape codes included.
+pub trait DisplayBackend: Write {}
+
+impl<W: Write + ?Sized> DisplayBackend for W {}
+
+/// Refreshes the display faster while package power is changing and slower while it is stable.
+pub struct AdaptiveRefreshRate {
+	pub min_interval_ms: u64,
+	pub max_interval_ms: u64,
+	pub change_threshold_watts: f64,
+	interval_ms: u64,
+	prev_displayed_pkg: Option<f64>,
+	stable_refreshes: u32,
+}
+
+impl Default for AdaptiveRefreshRate {
+	fn default() -> Self {
+		Self::new(&MonitorConfig::default())
+	}
+}
+
+impl AdaptiveRefreshRate {
+	/// Starts at the display interval of `config` and never refreshes faster than it samples.
+	#[must_use]
+	pub fn new(config: &MonitorConfig) -> Self {
+		Self {
+			min_interval_ms: config.collection_interval_ms,
+			max_interval_ms: MAX_ADAPTIVE_REFRESH_MS.max(config.display_interval_ms),
+			change_threshold_watts: 1.0,
+			interval_ms: config.display_interval_ms,
+			prev_displayed_pkg: None,
+			stable_refreshes: 0,
+		}
+	}
+
+	pub const fn interval(&self) -> Duration {
+		Duration::from_millis(self.interval_ms)
+	}
+
+	/// Adjusts the refresh interval after displaying `pkg_watts`.
+	pub fn record_displayed(&mut self, pkg_watts: f64) {
+		let changed = self
+			.prev_displayed_pkg
+			.is_some_and(|prev| (pkg_watts - prev).abs() > self.change_threshold_watts);
+		self.prev_displayed_pkg = Some(pkg_watts);
+
+		if changed {
+			self.interval_ms = (self.interval_ms / 2).max(self.min_interval_ms);
+			self.stable_refreshes = 0;
+			return;
+		}
+
+		self.stable_refreshes += 1;
+		if self.stable_refreshes >= STABLE_REFRESHES_BEFORE_SLOWDOWN {
+			self.interval_ms = (self.interval_ms * 2).min(self.max_interval_ms);
+			self.stable_refreshes = 0;
+		}
+	}
+}
+
+/// Formats power extremes as `Peak: 92.1 W at 00:05:23 | Trough: 3.2 W at 00:00:01`, with times
+/// relative to `started_at`.
+pub fn format_extremes(extremes: &PowerExtremes, started_at: Instant) -> String {
+	let format_point = |(power_mw, at): (f64, Instant)| {
+		let secs = at.saturating_duration_since(started_at).as_secs();
+		format!(
+			"{:.1} W at {:02}:{:02}:{:02}",
+			power_mw / 1000.0,
+			secs / 3600,
+			secs / 60 % 60,
+			secs % 60
+		)
+	};
+
+	match extremes {
+		(Some(min), Some(max)) => format!("Peak: {} | Trough: {}", format_point(*max), format_point(*min)),
+		_ => String::from("Peak: -- | Trough: --"),
+	}
+}
+
+/// Formats the energy of a session as `Total energy consumed: 12.34 kJ in 01:02:03`.
+pub fn format_session_energy(energy_joules: f64, elapsed: Duration) -> String {
+	let secs = elapsed.as_secs();
+	format!(
+		"Total energy consumed: {:.2} kJ in {:02}:{:02}:{:02}",
+		energy_joules / 1000.0,
+		secs / 3600,
+		secs / 60 % 60,
+		secs % 60
+	)
+}
+
+/// Formats package and core power of percentile readings as a table with a column per
+/// `(heading, reading)`, e.g. `("P95", …)`.
+pub fn format_percentile_table(columns: &[(&str, PowerReading)]) -> String {
+	let mut table = format!("{:<10}", "");
+	for (heading, _) in columns {
+		table.push_str(&format!("{heading:>10}"));
+	}
+	table.push_str(&format!("\n{:<10}", "Package"));
+	for (_, reading) in columns {
+		table.push_str(&format!("{:>8.2} W", reading.package));
+	}
+	let core_count = columns
+		.iter()
+		.map(|(_, reading)| reading.cores.len())
+		.max()
+		.unwrap_or(0);
+	for core in 0..core_count {
+		table.push_str(&format!("\n{:<10}", format!("Core {core}")));
+		for (_, reading) in columns {
+			match reading.cores.get(core).copied().flatten() {
+				Some(watts) => table.push_str(&format!("{watts:>8.2} W")),
+				None => table.push_str(&format!("{:>8} W", "--")),
+			}
+		}
+	}
+	table
+}
+
+/// Formats an energy budget as `Budget: 1.23 kJ / 4.56 kJ (27%)`.
+pub fn format_energy_budget(budget: &EnergyBudget) -> String {
+	format!(
+		"Budget: {:.2} kJ / {:.2} kJ ({:.0}%)",
+		budget.used_joules / 1000.0,
+		budget.budget_joules / 1000.0,
+		budget.used_fraction() * 100.0
+	)
+}
+
+/// Formats a session as `Session summary: total energy 12.34 kJ, average power 45.67 W, runtime 270
+/// seconds, EDP: 3331800.00 J·s`. The EDP is left out before any time has elapsed.
+pub fn format_session_summary(summary: &SessionSummary) -> String {
+	let mut formatted = format!(
+		"Session summary: total energy {:.2} kJ, average power {:.2} W, runtime {} seconds",
+		summary.total_energy_joules / 1000.0,
+		summary.pkg_avg_watts,
+		summary.elapsed.as_secs()
+	);
+	if let Some(edp) = summary.edp() {
+		formatted.push_str(&format!(", EDP: {:.2} J·s", edp.edp));
+	}
+	formatted
+}
+
+const HEADROOM_BAR_WIDTH: usize = 8;
+
+/// Formats the PL1 headroom as `Headroom: ████░░░░ 35%`, followed by `[PL2 BURST]` while package
+/// power is above PL2.
+pub fn format_power_limit_headroom(readings: &PowerReading, limits: &RaplLimits) -> String {
+	let fraction = if limits.pl1_watts > 0.0 {
+		(readings.pl1_headroom_watts(limits.pl1_watts) / limits.pl1_watts).clamp(0.0, 1.0)
+	} else {
+		0.0
+	};
+	let filled = (fraction * HEADROOM_BAR_WIDTH as f64).round() as usize;
+	let burst = if limits.pl2_watts > 0.0 && readings.package > limits.pl2_watts {
+		" [PL2 BURST]"
+	} else {
+		""
+	};
+	format!(
+		"Headroom: {}{} {:3.0}%{burst}",
+		"█".repeat(filled),
+		"░".repeat(HEADROOM_BAR_WIDTH - filled),
+		fraction * 100.0
+	)
+}
+
+const SPARKLINE_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const INTERVAL_HISTOGRAM_BINS: usize = 11;
+const INTERVAL_HISTOGRAM_BIN_MS: f64 = 10.0;
+
+/// Renders values as block characters scaled between their observed minimum and maximum.
+pub fn render_sparkline<'a>(values: impl IntoIterator<Item = &'a f64>) -> String {
+	let values: Vec<f64> = values.into_iter().copied().collect();
+	let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+	let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+	let range = max - min;
+
+	values
+		.iter()
+		.map(|&value| {
+			let level = if range > 0.0 {
+				((value - min) / range * (SPARKLINE_LEVELS.len() - 1) as f64).round() as usize
+			} else {
+				0
+			};
+			SPARKLINE_LEVELS[level.min(SPARKLINE_LEVELS.len() - 1)]
+		})
+		.collect()
+}
+
+/// Formats the distribution of actual sample durations in 10 ms bins centered on `target`, e.g.
+/// `Sample interval: [▁▁▃█▄▁▁] 98-115ms (target: 100ms)`.
+pub fn format_sample_interval_histogram<'a>(
+	intervals: impl IntoIterator<Item = &'a Duration>,
+	target: Duration,
+) -> String {
+	let intervals_ms: Vec<f64> = intervals.into_iter().map(|d| d.as_secs_f64() * 1000.0).collect();
+	let target_ms = target.as_secs_f64() * 1000.0;
+	if intervals_ms.is_empty() {
+		return format!("Sample interval: no samples (target: {target_ms:.0}ms)");
+	}
+
+	let center_bin = (INTERVAL_HISTOGRAM_BINS / 2) as f64;
+	let mut bins = [0.0; INTERVAL_HISTOGRAM_BINS];
+	for &interval_ms in &intervals_ms {
+		let bin = ((interval_ms - target_ms) / INTERVAL_HISTOGRAM_BIN_MS + center_bin).round();
+		bins[bin.clamp(0.0, (INTERVAL_HISTOGRAM_BINS - 1) as f64) as usize] += 1.0;
+	}
+
+	let min = intervals_ms.iter().copied().fold(f64::INFINITY, f64::min);
+	let max = intervals_ms.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+	format!(
+		"Sample interval: [{}] {min:.0}-{max:.0}ms (target: {target_ms:.0}ms)",
+		render_sparkline(&bins)
+	)
+}
+
+/// Columns of the terminal on stdout, from `TIOCGWINSZ`, or 80 when stdout is not a terminal.
+#[must_use]
+pub fn detect_terminal_width() -> usize {
+	let mut size = libc::winsize {
+		ws_row: 0,
+		ws_col: 0,
+		ws_xpixel: 0,
+		ws_ypixel: 0,
+	};
+	// SAFETY: TIOCGWINSZ only writes a winsize through the pointer
+	let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &raw mut size) };
+	if result == 0 && size.ws_col > 0 {
+		usize::from(size.ws_col)
+	} else {
+		DEFAULT_TERMINAL_WIDTH
+	}
+}
+
+/// Core cells that fit side by side on a line `terminal_width` columns wide, at least one.
+#[must_use]
+pub const fn cores_per_row(terminal_width: usize) -> usize {
+	let cores = terminal_width / CORE_COLUMN_WIDTH;
+	if cores == 0 { 1 } else { cores }
+}
+
+/// Layout of the terminal display for the current terminal width, with the recent package powers
+/// drawn as a sparkline at the end of the package line.
+#[derive(Debug, Clone)]
+pub struct DisplayLayout {
+	pub cores_per_row: usize,
+	/// Lines are cut at this width, as a wrapped line would throw off the redraw in place
+	terminal_width: usize,
+	/// Package watts of the latest redraws, at most `sparkline_width`
+	package_history: VecDeque<f64>,
+	sparkline_width: usize,
+}
+
+impl DisplayLayout {
+	#[must_use]
+	pub fn new(terminal_width: usize) -> Self {
+		let sparkline_width = terminal_width / SPARKLINE_WIDTH_DIVISOR;
+		Self {
+			cores_per_row: cores_per_row(terminal_width),
+			terminal_width,
+			package_history: VecDeque::with_capacity(sparkline_width),
+			sparkline_width,
+		}
+	}
+
+	/// Lays the display out again for a resized terminal. A narrower sparkline drops the oldest
+	/// readings, a wider one fills up with new ones.
+	pub fn resize(&mut self, terminal_width: usize) {
+		self.cores_per_row = cores_per_row(terminal_width);
+		self.terminal_width = terminal_width;
+		self.sparkline_width = terminal_width / SPARKLINE_WIDTH_DIVISOR;
+		while self.package_history.len() > self.sparkline_width {
+			self.package_history.pop_front();
+		}
+	}
+
+	/// Adds the package power of a redraw to the sparkline.
+	pub fn record_package(&mut self, watts: f64) {
+		self.package_history.push_back(watts);
+		if self.package_history.len() > self.sparkline_width {
+			self.package_history.pop_front();
+		}
+	}
+
+	/// Sparkline of the latest package powers, at most `columns` wide.
+	#[must_use]
+	pub fn package_sparkline(&self, columns: usize) -> String {
+		let skipped = self.package_history.len().saturating_sub(columns);
+		render_sparkline(self.package_history.iter().skip(skipped))
+	}
+}
+
+/// Cores displayed together, under a CCX header when CCXs were detected.
+pub(crate) fn core_groups(topology: &CpuTopology) -> Vec<(Option<usize>, Vec<usize>)> {
+	let ccx_groups = topology.ccx_groups();
+	if ccx_groups.is_empty() {
+		return vec![(None, (0..topology.physical_cores()).collect())];
+	}
+	ccx_groups.into_iter().map(|(ccx, cores)| (Some(ccx), cores)).collect()
+}
+
+/// Lines taken by the display: the package summary, the power limit line, the DRAM and platform
+/// line, a separator, one line per `cores_per_row` cores, a header per CCX and any status lines
+/// below them.
+pub fn display_line_count(topology: &CpuTopology, cores_per_row: usize, status_lines: usize) -> usize {
+	let core_lines: usize = core_groups(topology)
+		.iter()
+		.map(|(ccx, cores)| usize::from(ccx.is_some()) + cores.len().div_ceil(cores_per_row.max(1)))
+		.sum();
+	core_lines + 4 + status_lines
+}
+
+/// Reserves the lines the display redraws in place by moving the cursor up.
+pub fn prepare_display_area(
+	topology: &CpuTopology,
+	cores_per_row: usize,
+	status_lines: usize,
+	out: &mut impl DisplayBackend,
+) -> io::Result<usize> {
+	prepare_display_area_update(topology, cores_per_row, status_lines, 0, out)
+}
+
+/// Extends a display area of `previous_lines` lines to fit the current topology and terminal
+/// width without re-printing it. A shrinking display needs no new lines, the cursor just moves
+/// up less and the next redraw clears the lines left below it.
+pub fn prepare_display_area_update(
+	topology: &CpuTopology,
+	cores_per_row: usize,
+	status_lines: usize,
+	previous_lines: usize,
+	out: &mut impl DisplayBackend,
+) -> io::Result<usize> {
+	let new_lines = display_line_count(topology, cores_per_row, status_lines);
+	if new_lines > previous_lines {
+		for _ in previous_lines..new_lines {
+			writeln!(out)?;
+		}
+		out.flush()?;
+	}
+	Ok(new_lines)
+}
+
+/// Writes a reading as one line of JSON, e.g.
+/// `{"timestamp":"2024-05-01T13:45:10.250Z","package_watts":18.5,"estimated":false,"confidence":1,
+/// "cores":[{"id":0,"type":null,"watts":1.2}]}`. `estimated` is true when core powers were split
+/// from PP0 and `confidence` drops below 1 in a virtual machine. `type` is `"P-core"`, `"E-core"`
+/// or `"LP E-core"` on hybrid CPUs and `null` otherwise, and cores without a reading have `null`
+/// watts.
+pub fn write_json_reading(w: &mut impl Write, reading: &PowerReading) -> io::Result<()> {
+	let cores: Vec<String> = reading
+		.cores
+		.iter()
+		.enumerate()
+		.map(|(id, watts)| {
+			let watts = watts.map_or_else(|| String::from("null"), |watts| watts.to_string());
+			let core_type = reading
+				.core_types
+				.get(&id)
+				.map_or_else(|| String::from("null"), |core_type| escape_json(core_type.as_str()));
+			format!("{{\"id\":{id},\"type\":{core_type},\"watts\":{watts}}}")
+		})
+		.collect();
+	writeln!(
+		w,
+		"{{\"timestamp\":{},\"package_watts\":{},\"estimated\":{},\"confidence\":{},\"cores\":[{}]}}",
+		escape_json(&format_rfc3339(SystemTime::now())),
+		reading.package,
+		reading.cores_estimated,
+		reading.confidence,
+		cores.join(",")
+	)?;
+	w.flush()
+}
+
+/// Writes a reading as one line of `key=value` pairs after a timestamp, e.g.
+/// `2024-05-01T13:45:10.250Z package=18.50 core[0]=1.20 core[1]=--`. Cores without a reading
+/// are `--`.
+pub fn plain_print_reading(w: &mut impl Write, reading: &PowerReading) -> io::Result<()> {
+	write!(
+		w,
+		"{} package={:.2}",
+		format_rfc3339(SystemTime::now()),
+		reading.package
+	)?;
+	for (core_id, power) in reading.cores.iter().enumerate() {
+		match power {
+			Some(watts) => write!(w, " core[{core_id}]={watts:.2}")?,
+			None => write!(w, " core[{core_id}]=--")?,
+		}
+	}
+	writeln!(w)?;
+	w.flush()
+}
+
+/// Formats `watts` like the core cells, in green below half of `tdp`, yellow up to 80% of it
+/// and red above. Without a TDP the value is left uncolored.
+#[must_use]
+pub fn colorize_watts(watts: f64, tdp: Option<f64>) -> String {
+	let formatted = format!("{watts:5.2} W");
+	let color = match tdp.filter(|&tdp| tdp > 0.0).map(|tdp| watts / tdp) {
+		None => return formatted,
+		Some(ratio) if ratio > TDP_RED_RATIO => ANSI_RED,
+		Some(ratio) if ratio >= TDP_YELLOW_RATIO => ANSI_YELLOW,
+		Some(_) => ANSI_GREEN,
+	};
+	format!("{color}{formatted}{ANSI_RESET}")
+}
+
+/// Writes a reading as one short line for status bars such as Waybar or i3status:
+/// `PKG:18.5W P:12.3W E:6.2W`, or `{"pkg":18.5,"p":12.3,"e":6.2}` if `json`, with the summed
+/// power of the P-cores and of the E-cores, low-power ones included. CPUs without hybrid cores
+/// get the sum of all cores instead, as `PKG:18.5W CORES:12.3W` or `{"pkg":18.5,"cores":12.3}`.
+pub fn write_compact_reading(w: &mut impl Write, reading: &PowerReading, json: bool) -> io::Result<()> {
+	if reading.core_types.is_empty() {
+		let cores: f64 = reading.cores.iter().flatten().sum();
+		if json {
+			writeln!(w, "{{\"pkg\":{:.1},\"cores\":{cores:.1}}}", reading.package)?;
+		} else {
+			writeln!(w, "PKG:{:.1}W CORES:{cores:.1}W", reading.package)?;
+		}
+		return w.flush();
+	}
+
+	let (mut p_cores, mut e_cores) = (0.0, 0.0);
+	for (core_id, watts) in reading.cores.iter().enumerate() {
+		match (reading.core_types.get(&core_id), watts) {
+			(Some(CoreType::Performance), Some(watts)) => p_cores += watts,
+			(Some(CoreType::Efficiency | CoreType::LowPowerEfficiency), Some(watts)) => e_cores += watts,
+			_ => {},
+		}
+	}
+	if json {
+		writeln!(
+			w,
+			"{{\"pkg\":{:.1},\"p\":{p_cores:.1},\"e\":{e_cores:.1}}}",
+			reading.package
+		)?;
+	} else {
+		writeln!(w, "PKG:{:.1}W P:{p_cores:.1}W E:{e_cores:.1}W", reading.package)?;
+	}
+	w.flush()
+}
+
+/// Columns `line` takes on the terminal, not counting ANSI escape sequences.
+fn visible_len(line: &str) -> usize {
+	let mut len = 0;
+	let mut chars = line.chars();
+	while let Some(c) = chars.next() {
+		if c == '\x1B' {
+			// Skips the CSI sequence up to its final letter
+			chars.find(char::is_ascii_alphabetic);
+		} else {
+			len += 1;
+		}
+	}
+	len
+}
+
+/// Clears the current line and writes `line` on it, cut at `width` columns so it never wraps.
+fn write_display_line(out: &mut impl DisplayBackend, line: &str, width: usize) -> io::Result<()> {
+	write!(out, "\x1B[2K")?;
+	if visible_len(line) <= width {
+		return writeln!(out, "{line}");
+	}
+
+	let mut cut = String::new();
+	let mut len = 0;
+	let mut chars = line.chars();
+	while let Some(c) = chars.next() {
+		if c == '\x1B' {
+			// Keeps every escape sequence, so colors are still reset after the cut
+			cut.push(c);
+			for c in chars.by_ref() {
+				cut.push(c);
+				if c.is_ascii_alphabetic() {
+					break;
+				}
+			}
+		} else if len < width {
+			cut.push(c);
+			len += 1;
+		}
+	}
+	writeln!(out, "{cut}")
+}
+
+fn format_core_power(power: Option<f64>, core_tdp: Option<f64>) -> String {
+	power.map_or_else(|| format!("{:>5} W", "--"), |watts| colorize_watts(watts, core_tdp))
+}
+
+/// Redraws the display area in place, laid out by `layout`.
+pub fn display_power_readings(
+	readings: &PowerReading,
+	topology: &CpuTopology,
+	layout: &DisplayLayout,
+	hwp: Option<&HwpCapabilities>,
+	limits: Option<&RaplLimits>,
+	status_lines: &[String],
+	out: &mut impl DisplayBackend,
+) -> io::Result<()> {
+	let cores_per_row = layout.cores_per_row;
+	let width = layout.terminal_width;
+	let total_lines = display_line_count(topology, cores_per_row, status_lines.len());
+	write!(out, "\x1B[{total_lines}A")?;
+
+	let headroom_str = hwp.map(|hwp| {
+		format!(
+			"Boost headroom: {:3.0}% (perf {}-{})",
+			hwp.boost_headroom_percent(),
+			hwp.lowest_perf,
+			hwp.max_perf
+		)
+	});
+
+	let variable_str = if readings.is_variable() { " [VARIABLE]" } else { "" };
+	let active_str = readings
+		.c0_fraction
+		.map_or_else(String::new, |c0| format!(" | Active: {:3.0}%", c0 * 100.0));
+	let package_color =
+		limits
+			.filter(|limits| limits.pl1_watts > 0.0)
+			.and_then(|limits| match readings.package / limits.pl1_watts {
+				ratio if ratio >= 1.0 => Some(ANSI_RED),
+				ratio if ratio >= PL1_WARNING_RATIO => Some(ANSI_YELLOW),
+				_ => None,
+			});
+	let package_str = match package_color {
+		_ if readings.quantization_limited => format!("{:>6}", "[QTZ]"),
+		Some(color) => format!("{color}{:6.2}{ANSI_RESET}", readings.package),
+		None => format!("{:6.2}", readings.package),
+	};
+	let tdp_str = limits.map_or_else(String::new, |limits| format!(" / {:.0} W TDP", limits.pl1_watts));
+	let mut package_line = format!(
+		"Package: {} ± {:4.1} W{}{} | Cores Total{}: {:6.2} W{}",
+		package_str,
+		readings.pkg_stddev_watts,
+		tdp_str,
+		variable_str,
+		if readings.cores_estimated { " (est.)" } else { "" },
+		readings.cores.iter().flatten().sum::<f64>(),
+		active_str,
+	);
+	// The sparkline gets what the figures leave of the line, after a space
+	let sparkline_columns = width.saturating_sub(visible_len(&package_line) + 1);
+	if sparkline_columns >= MIN_SPARKLINE_WIDTH {
+		let sparkline = layout.package_sparkline(sparkline_columns);
+		if !sparkline.is_empty() {
+			package_line.push(' ');
+			package_line.push_str(&sparkline);
+		}
+	}
+	write_display_line(out, &package_line, width)?;
+
+	let limit_line: Vec<String> = limits
+		.map(|limits| format_power_limit_headroom(readings, limits))
+		.into_iter()
+		.chain(headroom_str)
+		.collect();
+	write_display_line(out, &limit_line.join(" | "), width)?;
+
+	let mut memory_and_platform = Vec::new();
+	if let Some(dram) = readings.dram_watts {
+		memory_and_platform.push(format!("DRAM:    {dram:6.2} W"));
+	}
+	if let Some(platform) = readings.platform_watts {
+		memory_and_platform.push(format!("Platform: {platform:6.2} W"));
+	}
+	write_display_line(out, &memory_and_platform.join(" | "), width)?;
+
+	let mut domains = Vec::new();
+	// A single socket's power is the package power
+	if readings.sockets.len() > 1 {
+		let mut sockets: Vec<(&usize, &f64)> = readings.sockets.iter().collect();
+		sockets.sort_unstable_by_key(|&(&socket, _)| socket);
+		domains.extend(
+			sockets
+				.into_iter()
+				.map(|(socket, watts)| format!("Socket {socket}: {watts:6.2} W")),
+		);
+	}
+	if let Some(uncore) = readings.uncore_power_estimate() {
+		domains.push(format!("Uncore: {uncore:6.2} W"));
+	}
+	if let Some(pp1) = readings.pp1_watts {
+		domains.push(format!("Uncore/GPU: {pp1:6.2} W"));
+	}
+	write_display_line(out, &domains.join(" | "), width)?;
+
+	// Each core is colored against an even share of PL1, unless escape codes would end up in a
+	// pipe
+	let core_tdp = limits
+		.filter(|limits| limits.pl1_watts > 0.0 && io::stdout().is_terminal())
+		.map(|limits| limits.pl1_watts / topology.physical_cores().max(1) as f64);
+	// Preferred cores get a badge, the others padding to keep the columns aligned
+	let format_core = |core: usize| {
+		let badge = if topology.preferred_cores.contains(&core) {
+			" [★]"
+		} else if topology.preferred_cores.is_empty() {
+			""
+		} else {
+			"    "
+		};
+		let temperature = match readings.temperatures.get(&core) {
+			Some(celsius) => format!(" ({celsius:3}°C)"),
+			None if readings.temperatures.is_empty() => String::new(),
+			None => format!(" ({:>3}°C)", "--"),
+		};
+		let frequency = match readings.frequencies.get(&core) {
+			Some(mhz) => format!(" @ {mhz:4} MHz"),
+			None if readings.frequencies.is_empty() => String::new(),
+			None => format!(" @ {:>4} MHz", "--"),
+		};
+		if readings.offline_cores.contains(&core) {
+			return format!("(offline){badge}");
+		}
+		format!(
+			"{}{frequency}{temperature}{badge}",
+			format_core_power(readings.cores.get(core).copied().flatten(), core_tdp)
+		)
+	};
+
+	for (ccx, cores) in core_groups(topology) {
+		if let Some(ccx) = ccx {
+			let ccx_watts: f64 = cores
+				.iter()
+				.filter_map(|&core| readings.cores.get(core).copied().flatten())
+				.sum();
+			write_display_line(out, &format!("CCX {ccx}: {ccx_watts:6.2} W"), width)?;
+		}
+
+		for row in cores.chunks(cores_per_row.max(1)) {
+			let cells: Vec<String> = row
+				.iter()
+				.map(|&core| format!("{:<9} {}", format!("Core {core}:"), format_core(core)))
+				.collect();
+			write_display_line(out, &cells.join(" | "), width)?;
+		}
+	}
+
+	for line in status_lines {
+		write_display_line(out, line, width)?;
+	}
+
+	// Lines left below by a display that got shorter, e.g. after the terminal was widened
+	write!(out, "\x1B[J")?;
+	out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::{HashMap, HashSet};
+
+	fn reading(package: f64, cores: Vec<Option<f64>>) -> PowerReading {
+		PowerReading {
+			package,
+			pkg_stddev_watts: 0.5,
+			quantization_limited: false,
+			c0_fraction: None,
+			cores,
+			cores_estimated: false,
+			temperatures: HashMap::new(),
+			frequencies: HashMap::new(),
+			energy_perf_preferences: HashMap::new(),
+			core_types: HashMap::new(),
+			offline_cores: HashSet::new(),
+			pp0_watts: None,
+			pp1_watts: None,
+			dram_watts: None,
+			platform_watts: None,
+			sockets: HashMap::new(),
+			energy_budget: None,
+			confidence: 1.0,
+		}
+	}
+
+	/// Renders `reading` into a buffer and returns the redrawn lines without the escape codes
+	/// that move the cursor up, clear each line and clear the lines left below.
+	fn render(reading: &PowerReading, topology: &CpuTopology, terminal_width: usize) -> Vec<String> {
+		render_with(reading, topology, &DisplayLayout::new(terminal_width), None, None)
+	}
+
+	fn render_with(
+		reading: &PowerReading,
+		topology: &CpuTopology,
+		layout: &DisplayLayout,
+		hwp: Option<&HwpCapabilities>,
+		limits: Option<&RaplLimits>,
+	) -> Vec<String> {
+		let mut out = Vec::new();
+		display_power_readings(reading, topology, layout, hwp, limits, &[], &mut out).unwrap();
+		let out = String::from_utf8(out).unwrap();
+		let total_lines = display_line_count(topology, layout.cores_per_row, 0);
+		let redraw = out
+			.strip_prefix(&format!("\x1B[{total_lines}A"))
+			.and_then(|out| out.strip_suffix("\x1B[J"))
+			.unwrap();
+		redraw.lines().map(|line| line.replace("\x1B[2K", "")).collect()
+	}
+
+	#[test]
+	fn renders_package_and_core_lines() {
+		let topology = CpuTopology::from_core_count(2, 2);
+		let lines = render(&reading(18.5, vec![Some(1.2), None]), &topology, 200);
+
+		assert_eq!(lines.len(), 5);
+		assert_eq!(lines[0], "Package:  18.50 ±  0.5 W | Cores Total:   1.20 W");
+		assert_eq!(lines[4], "Core 0:    1.20 W | Core 1:      -- W");
+	}
+
+	#[test]
+	fn wraps_cores_at_the_terminal_width() {
+		let topology = CpuTopology::from_core_count(4, 4);
+		let lines = render(&reading(10.0, vec![Some(1.0); 4]), &topology, 2 * CORE_COLUMN_WIDTH);
+
+		assert_eq!(lines.len(), 6);
+		assert_eq!(lines[4], "Core 0:    1.00 W | Core 1:    1.00 W");
+		assert_eq!(lines[5], "Core 2:    1.00 W | Core 3:    1.00 W");
+	}
+
+	#[test]
+	fn fits_limits_and_sparkline_in_80_columns() {
+		let topology = CpuTopology::from_core_count(4, 4);
+		let reading = reading(42.5, vec![Some(10.0); 4]);
+		let mut layout = DisplayLayout::new(80);
+		for watts in [30.0, 35.0, 42.5, 40.0, 38.0, 45.0, 41.0, 39.0, 42.5, 44.0] {
+			layout.record_package(watts);
+		}
+		let hwp = HwpCapabilities {
+			max_perf: 255,
+			guaranteed_perf: 128,
+			lowest_perf: 1,
+		};
+		let limits = RaplLimits {
+			pl1_watts: 125.0,
+			pl2_watts: 253.0,
+		};
+		let lines = render_with(&reading, &topology, &layout, Some(&hwp), Some(&limits));
+
+		assert!(lines[0].ends_with(" ▁▃▇▆▅█▆▅▇█"), "{}", lines[0]);
+		assert!(lines[1].starts_with("Headroom: "));
+		for line in &lines {
+			assert!(visible_len(line) <= 80, "{line}");
+		}
+	}
+
+	#[test]
+	fn cuts_lines_at_the_terminal_width() {
+		let mut out = Vec::new();
+		write_display_line(&mut out, &format!("{ANSI_RED}123456{ANSI_RESET}789"), 4).unwrap();
+
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			format!("\x1B[2K{ANSI_RED}1234{ANSI_RESET}\n")
+		);
+	}
+
+	#[test]
+	fn json_reading_names_hybrid_core_types() {
+		let mut reading = reading(12.0, vec![Some(3.0), None]);
+		reading.core_types.insert(0, CoreType::Performance);
+		let mut out = Vec::new();
+		write_json_reading(&mut out, &reading).unwrap();
+		let json = String::from_utf8(out).unwrap();
+
+		assert!(json.contains(r#""cores":[{"id":0,"type":"P-core","watts":3},{"id":1,"type":null,"watts":null}]"#));
+	}
+
+	#[test]
+	fn compact_reading_splits_hybrid_cores() {
+		let mut reading = reading(18.5, vec![Some(6.0), Some(6.3), Some(4.0), Some(2.2)]);
+		reading.core_types = HashMap::from([
+			(0, CoreType::Performance),
+			(1, CoreType::Performance),
+			(2, CoreType::Efficiency),
+			(3, CoreType::LowPowerEfficiency),
+		]);
+		let compact = |json| {
+			let mut out = Vec::new();
+			write_compact_reading(&mut out, &reading, json).unwrap();
+			String::from_utf8(out).unwrap()
+		};
+
+		assert_eq!(compact(false), "PKG:18.5W P:12.3W E:6.2W\n");
+		assert_eq!(compact(true), "{\"pkg\":18.5,\"p\":12.3,\"e\":6.2}\n");
+	}
+
+	#[test]
+	fn compact_reading_sums_cores_without_hybrid_cores() {
+		let mut out = Vec::new();
+		write_compact_reading(&mut out, &reading(18.5, vec![Some(6.0), None, Some(6.3)]), false).unwrap();
+
+		assert_eq!(String::from_utf8(out).unwrap(), "PKG:18.5W CORES:12.3W\n");
+	}
+
+	#[test]
+	fn shows_cores_missing_from_the_reading_without_power() {
+		// A replayed reading taken after the topology shrank
+		let topology = CpuTopology::from_core_count(2, 2);
+		let lines = render(&reading(5.0, vec![Some(2.0)]), &topology, 200);
+
+		assert_eq!(lines[4], "Core 0:    2.00 W | Core 1:      -- W");
+	}
+
+	#[test]
+	fn marks_offline_cores() {
+		let topology = CpuTopology::from_core_count(2, 2);
+		let mut reading = reading(5.0, vec![Some(2.0), None]);
+		reading.offline_cores.insert(1);
+		let lines = render(&reading, &topology, 200);
+
+		assert_eq!(lines[4], "Core 0:    2.00 W | Core 1:   (offline)");
+	}
+}
@@ -0,0 +1,613 @@
+use crate::freq::read_core_frequencies;
+use crate::hwp::read_core_energy_perf_preferences;
+use crate::online::{offline_cores, online_threads, read_online_cpus};
+use crate::perf::PerfRapl;
+use crate::powercap::PowercapZones;
+use crate::thermal::read_core_temperatures;
+use crate::topology::CpuTopology;
+use crate::{
+	AMD_ENERGY_CORE_MSR, AMD_ENERGY_PKG_MSR, CoreType, CounterWidth, CpuType, IA32_APERF_MSR, IA32_MPERF_MSR,
+	IA32_TSC_MSR, INTEL_CORE_ENERGY_MSR, INTEL_DRAM_ENERGY_MSR, INTEL_PKG_ENERGY_MSR, INTEL_PP1_ENERGY_MSR,
+	INTEL_PSYS_ENERGY_MSR, POWER_SCALE, get_energy_counter_mask, get_energy_unit, read_msr,
+};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use std::{io, thread};
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnergySnapshot {
+	pub package: u64,
+	pub cores: Vec<Option<u64>>,
+	/// Intel PP0 (all cores) domain
+	pub pp0: Option<u64>,
+	/// Intel PP1 domain, the integrated GPU on client parts
+	pub pp1: Option<u64>,
+	/// Intel DRAM domain, on the SKUs that have it
+	pub dram: Option<u64>,
+	/// Intel PSys domain: the whole platform, including the PCH, DRAM and VR losses, on client
+	/// parts from Skylake on. Counted once for the platform, not per socket.
+	pub platform: Option<u64>,
+	/// Package counter of each socket as `(socket, counter)`. `package` is their sum.
+	pub sockets: Vec<(usize, u64)>,
+	/// Summed APERF of each Intel core's threads, for splitting PP0 across the cores
+	pub core_aperf: Vec<Option<u64>>,
+	/// Reads that failed without aborting the snapshot, such as a core going offline
+	#[cfg_attr(feature = "serde", serde(skip))]
+	pub msr_errors: Vec<MsrReadError>,
+	/// `(MPERF, TSC)` of the boot CPU, for C0 residency
+	pub c0_counters: Option<(u64, u64)>,
+	/// Taken right after the last MSR read, so deltas between snapshots cover exactly the
+	/// measured energy. Not serialized, as an `Instant` is only meaningful within the process.
+	#[cfg_attr(feature = "serde", serde(skip, default = "Instant::now"))]
+	pub captured_at: Instant,
+}
+
+impl EnergySnapshot {
+	/// Combines per-socket snapshots into one: package and PP0 counters are summed, cores are
+	/// concatenated so the cores of later sockets follow those of earlier ones, and the earliest
+	/// capture time is kept. Summed counters still give correct deltas under the counter mask as
+	/// long as the combined energy of an interval fits in it.
+	pub fn merge<'a>(snapshots: impl IntoIterator<Item = &'a Self>) -> Self {
+		let mut merged = Self {
+			package: 0,
+			cores: Vec::new(),
+			pp0: Some(0),
+			pp1: Some(0),
+			dram: Some(0),
+			platform: None,
+			sockets: Vec::new(),
+			core_aperf: Vec::new(),
+			msr_errors: Vec::new(),
+			c0_counters: None,
+			captured_at: Instant::now(),
+		};
+		for snapshot in snapshots {
+			merged.package = merged.package.wrapping_add(snapshot.package);
+			merged.cores.extend_from_slice(&snapshot.cores);
+			merged.pp0 = merged.pp0.zip(snapshot.pp0).map(|(total, pp0)| total.wrapping_add(pp0));
+			merged.pp1 = merged.pp1.zip(snapshot.pp1).map(|(total, pp1)| total.wrapping_add(pp1));
+			merged.dram = merged
+				.dram
+				.zip(snapshot.dram)
+				.map(|(total, dram)| total.wrapping_add(dram));
+			// Every socket reads the same platform counter
+			merged.platform = merged.platform.or(snapshot.platform);
+			merged.sockets.extend_from_slice(&snapshot.sockets);
+			merged.core_aperf.extend_from_slice(&snapshot.core_aperf);
+			merged.msr_errors.extend_from_slice(&snapshot.msr_errors);
+			merged.c0_counters = merged.c0_counters.or(snapshot.c0_counters);
+			merged.captured_at = merged.captured_at.min(snapshot.captured_at);
+		}
+		merged
+	}
+
+	#[must_use]
+	pub fn elapsed_since(&self, earlier: &Self) -> Duration {
+		self.captured_at.saturating_duration_since(earlier.captured_at)
+	}
+
+	/// Raw counter steps of every domain since `earlier`, for calculations other than the average
+	/// power of [`Self::power_since`].
+	#[must_use]
+	pub fn diff(&self, earlier: &Self, counter_mask: u64) -> EnergyDiff {
+		let delta = |start: Option<u64>, end: Option<u64>| Some(energy_delta(start?, end?, counter_mask));
+		EnergyDiff {
+			package: energy_delta(earlier.package, self.package, counter_mask),
+			cores: earlier
+				.cores
+				.iter()
+				.zip(self.cores.iter())
+				.map(|(&start, &end)| delta(start, end))
+				.collect(),
+			pp0: delta(earlier.pp0, self.pp0),
+			pp1: delta(earlier.pp1, self.pp1),
+			dram: delta(earlier.dram, self.dram),
+			platform: delta(earlier.platform, self.platform),
+			interval: self.elapsed_since(earlier),
+		}
+	}
+
+	/// Average power in mW of every domain between `initial` and this snapshot.
+	#[must_use]
+	pub fn power_since(&self, initial: &Self, energy_unit: u64, counter_mask: u64) -> PowerSample {
+		let interval = self.elapsed_since(initial);
+		let power = |start: Option<u64>, end: Option<u64>| {
+			Some(calculate_power_mw_f64(
+				start?,
+				end?,
+				interval,
+				energy_unit,
+				counter_mask,
+			))
+		};
+
+		let pp0 = power(initial.pp0, self.pp0);
+		let (cores, cores_estimated) =
+			match pp0.and_then(|pp0| split_by_aperf(pp0, &initial.core_aperf, &self.core_aperf)) {
+				Some(estimates) => (estimates, true),
+				None => (
+					initial
+						.cores
+						.iter()
+						.zip(self.cores.iter())
+						.map(|(&start, &end)| power(start, end))
+						.collect(),
+					false,
+				),
+			};
+
+		PowerSample {
+			package: calculate_power_mw_f64(initial.package, self.package, interval, energy_unit, counter_mask),
+			cores,
+			cores_estimated,
+			pp0,
+			pp1: power(initial.pp1, self.pp1),
+			dram: power(initial.dram, self.dram),
+			platform: power(initial.platform, self.platform),
+			sockets: initial
+				.sockets
+				.iter()
+				.zip(self.sockets.iter())
+				.filter(|((start_socket, _), (end_socket, _))| start_socket == end_socket)
+				.map(|(&(socket, start), &(_, end))| {
+					let power = calculate_power_mw_f64(start, end, interval, energy_unit, counter_mask);
+					(socket, power)
+				})
+				.collect(),
+			interval,
+			msr_errors: initial.msr_errors.iter().chain(&self.msr_errors).cloned().collect(),
+			quantization_limited: energy_delta(initial.package, self.package, counter_mask) == 0,
+			c0_fraction: initial
+				.c0_counters
+				.zip(self.c0_counters)
+				.and_then(|((mperf0, tsc0), (mperf1, tsc1))| {
+					let tsc_delta = tsc1.wrapping_sub(tsc0);
+					(tsc_delta > 0).then(|| (mperf1.wrapping_sub(mperf0) as f64 / tsc_delta as f64).clamp(0.0, 1.0))
+				}),
+			temperatures: HashMap::new(),
+			frequencies: HashMap::new(),
+			energy_perf_preferences: HashMap::new(),
+			core_types: HashMap::new(),
+			offline_cores: HashSet::new(),
+		}
+	}
+}
+
+/// A failed MSR read, kept as kind and message since `io::Error` cannot be cloned.
+#[derive(Debug, Clone)]
+pub struct MsrReadError {
+	pub kind: io::ErrorKind,
+	pub message: String,
+}
+
+impl From<&io::Error> for MsrReadError {
+	fn from(error: &io::Error) -> Self {
+		Self {
+			kind: error.kind(),
+			message: error.to_string(),
+		}
+	}
+}
+
+impl From<&MsrReadError> for io::Error {
+	fn from(error: &MsrReadError) -> Self {
+		Self::new(error.kind, error.message.clone())
+	}
+}
+
+/// Energy counter steps between two snapshots. One step is `1 / 2^energy_unit` J.
+#[derive(Debug, Clone)]
+pub struct EnergyDiff {
+	pub package: u64,
+	pub cores: Vec<Option<u64>>,
+	pub pp0: Option<u64>,
+	pub pp1: Option<u64>,
+	pub dram: Option<u64>,
+	pub platform: Option<u64>,
+	pub interval: Duration,
+}
+
+/// Power in mW measured between two energy snapshots.
+pub struct PowerSample {
+	pub package: f64,
+	pub cores: Vec<Option<f64>>,
+	/// Core powers are PP0 split by APERF rather than per-core energy counters
+	pub cores_estimated: bool,
+	pub pp0: Option<f64>,
+	pub pp1: Option<f64>,
+	pub dram: Option<f64>,
+	/// Intel PSys (whole platform) power
+	pub platform: Option<f64>,
+	/// Package power of each socket, empty when read through powercap
+	pub sockets: HashMap<usize, f64>,
+	pub interval: Duration,
+	/// Failed reads of both snapshots
+	pub msr_errors: Vec<MsrReadError>,
+	/// The package counter did not move by a single energy unit, so the interval was too short
+	/// for the counter resolution and the package power of 0 is not a real measurement
+	pub quantization_limited: bool,
+	/// Fraction of the interval the boot CPU spent in C0
+	pub c0_fraction: Option<f64>,
+	/// Core temperatures in °C at the end of the interval
+	pub temperatures: HashMap<usize, u8>,
+	/// Core frequencies in MHz at the end of the interval
+	pub frequencies: HashMap<usize, u32>,
+	/// HWP energy-performance preference of each core at the end of the interval
+	pub energy_perf_preferences: HashMap<usize, u8>,
+	/// Type of each core of a hybrid CPU, from the topology
+	pub core_types: HashMap<usize, CoreType>,
+	/// Physical cores with no online thread at the end of the interval
+	pub offline_cores: HashSet<usize>,
+}
+
+/// Mask of the 32-bit energy counters documented for both vendors.
+pub const ENERGY_COUNTER_MASK_32: u64 = 0xFFFF_FFFF;
+
+/// Counter steps between two reads of an energy counter `counter_mask` wide, accounting for
+/// wraparound.
+const fn energy_delta(energy_start: u64, energy_end: u64, counter_mask: u64) -> u64 {
+	energy_end.wrapping_sub(energy_start) & counter_mask
+}
+
+/// Average power in µW between two reads of an energy counter `width` wide, such as the width
+/// from [`crate::get_energy_counter_width`].
+#[must_use]
+pub const fn calculate_power_uw(
+	energy_start: u64,
+	energy_end: u64,
+	interval: Duration,
+	energy_unit: u64,
+	width: CounterWidth,
+) -> u64 {
+	calculate_power_uw_with_mask(energy_start, energy_end, interval, energy_unit, width.mask())
+}
+
+#[must_use]
+pub const fn calculate_power_uw_with_mask(
+	energy_start: u64,
+	energy_end: u64,
+	interval: Duration,
+	energy_unit: u64,
+	counter_mask: u64,
+) -> u64 {
+	let energy_difference = energy_delta(energy_start, energy_end, counter_mask);
+
+	let interval_us = interval.as_micros() as u64;
+	if interval_us == 0 {
+		return 0;
+	}
+
+	let energy_uj = (energy_difference * POWER_SCALE) >> energy_unit;
+	energy_uj * 1_000_000 / interval_us
+}
+
+/// Like [`calculate_power_uw`], but in floating point throughout, so short intervals and small
+/// powers keep their sub-microwatt precision.
+#[must_use]
+pub fn calculate_power_mw_f64(
+	energy_start: u64,
+	energy_end: u64,
+	elapsed: Duration,
+	energy_unit: u64,
+	counter_mask: u64,
+) -> f64 {
+	let elapsed_s = elapsed.as_secs_f64();
+	if elapsed_s == 0.0 {
+		return 0.0;
+	}
+
+	let energy_joules = energy_delta(energy_start, energy_end, counter_mask) as f64 / (1u64 << energy_unit) as f64;
+	energy_joules / elapsed_s * 1000.0
+}
+
+/// Reads a core's energy counter through the first of its threads that answers, so an offline
+/// thread 0 does not hide the core.
+pub fn read_core_energy_msr(core_id: usize, threads: &[usize]) -> io::Result<u64> {
+	let mut last_error = None;
+	for &thread_id in threads {
+		match read_msr(AMD_ENERGY_CORE_MSR, thread_id) {
+			Ok(energy) => return Ok(energy),
+			Err(e) => last_error = Some(e),
+		}
+	}
+	Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Core {core_id} has no threads"))))
+}
+
+/// Energy counter of a core read through its first online thread that responds, or `None` when
+/// none of its threads is online.
+fn read_online_core_counter(
+	core_id: usize,
+	threads: &[usize],
+	online: Option<&HashSet<usize>>,
+) -> Option<io::Result<u64>> {
+	let threads = online_threads(threads, online);
+	(!threads.is_empty()).then(|| read_core_energy_msr(core_id, &threads))
+}
+
+/// Energy counters of every AMD core as `(core, counter)` in core order, read concurrently so
+/// the snapshot spans as little time as possible on many-core parts.
+#[cfg(feature = "rayon")]
+fn read_amd_core_counters(
+	topology: &CpuTopology,
+	online: Option<&HashSet<usize>>,
+) -> Vec<(usize, Option<io::Result<u64>>)> {
+	use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+	topology
+		.core_to_threads
+		.par_iter()
+		.map(|(&core_id, threads)| (core_id, read_online_core_counter(core_id, threads, online)))
+		.collect()
+}
+
+/// Energy counters of every AMD core as `(core, counter)` in core order, one core after the
+/// other. The `rayon` feature reads them concurrently.
+#[cfg(not(feature = "rayon"))]
+fn read_amd_core_counters(
+	topology: &CpuTopology,
+	online: Option<&HashSet<usize>>,
+) -> Vec<(usize, Option<io::Result<u64>>)> {
+	topology
+		.core_to_threads
+		.iter()
+		.map(|(&core_id, threads)| (core_id, read_online_core_counter(core_id, threads, online)))
+		.collect()
+}
+
+/// Sum of the APERF counters of a core's threads.
+fn read_core_aperf(threads: &[usize]) -> io::Result<u64> {
+	threads.iter().try_fold(0u64, |total, &thread_id| {
+		Ok(total.wrapping_add(read_msr(IA32_APERF_MSR, thread_id)?))
+	})
+}
+
+/// Splits PP0 across the cores in proportion to the cycles each executed, which grow with both
+/// time in C0 and frequency. `None` unless every core's APERF was read in both snapshots and some
+/// core ran.
+fn split_by_aperf(pp0_mw: f64, initial: &[Option<u64>], latest: &[Option<u64>]) -> Option<Vec<Option<f64>>> {
+	if initial.is_empty() || initial.len() != latest.len() {
+		return None;
+	}
+	let cycles: Vec<u64> = initial
+		.iter()
+		.zip(latest)
+		.map(|(&start, &end)| Some(end?.wrapping_sub(start?)))
+		.collect::<Option<_>>()?;
+	let total_cycles = cycles.iter().map(|&c| c as f64).sum::<f64>();
+	(total_cycles > 0.0).then(|| cycles.iter().map(|&c| Some(pp0_mw * c as f64 / total_cycles)).collect())
+}
+
+/// Reads MPERF and TSC of the boot CPU. MPERF only advances in C0, so their deltas give the
+/// active fraction of an interval, unlike APERF, which also scales with the current frequency.
+fn read_c0_counters() -> Option<(u64, u64)> {
+	Some((read_msr(IA32_MPERF_MSR, 0).ok()?, read_msr(IA32_TSC_MSR, 0).ok()?))
+}
+
+/// Package-scoped counters of one socket, read through its CPU `cpu`, for merging with the other
+/// sockets. Not every Intel SKU has the PP0, PP1 and DRAM domains, and AMD has none of them.
+fn read_socket_snapshot(cpu_type: &CpuType, socket: usize, cpu: usize) -> io::Result<EnergySnapshot> {
+	let (package_msr, optional_domains) = match cpu_type {
+		CpuType::Intel => (INTEL_PKG_ENERGY_MSR, true),
+		CpuType::Amd => (AMD_ENERGY_PKG_MSR, false),
+		CpuType::Unsupported => return Err(io::Error::new(io::ErrorKind::Unsupported, "Unsupported CPU type")),
+	};
+	let package = read_msr(package_msr, cpu)?;
+	let optional_domain = |msr_address| optional_domains.then(|| read_msr(msr_address, cpu).ok()).flatten();
+	Ok(EnergySnapshot {
+		package,
+		cores: Vec::new(),
+		pp0: optional_domain(INTEL_CORE_ENERGY_MSR),
+		pp1: optional_domain(INTEL_PP1_ENERGY_MSR),
+		dram: optional_domain(INTEL_DRAM_ENERGY_MSR),
+		platform: None,
+		sockets: vec![(socket, package)],
+		core_aperf: Vec::new(),
+		msr_errors: Vec::new(),
+		c0_counters: None,
+		captured_at: Instant::now(),
+	})
+}
+
+/// Package-scoped energy counters are read once per socket and merged with
+/// [`EnergySnapshot::merge`], so multi-socket systems measure every package. Core counters are
+/// then read across the whole topology, in core order.
+pub fn read_energy_snapshot(cpu_type: &CpuType, topology: &CpuTopology) -> io::Result<EnergySnapshot> {
+	let sockets = topology
+		.package_cpus()
+		.into_iter()
+		.map(|(socket, cpu)| read_socket_snapshot(cpu_type, socket, cpu))
+		.collect::<io::Result<Vec<_>>>()?;
+	let mut snapshot = EnergySnapshot::merge(&sockets);
+	// Threads taken offline since the topology was detected have no readable MSRs
+	let online = read_online_cpus().ok();
+	match cpu_type {
+		CpuType::Intel => {
+			snapshot.cores = vec![snapshot.pp0];
+			snapshot.platform = read_msr(INTEL_PSYS_ENERGY_MSR, 0).ok();
+			snapshot.core_aperf = topology
+				.core_to_threads
+				.values()
+				.map(|threads| read_core_aperf(&online_threads(threads, online.as_ref())).ok())
+				.collect();
+		},
+		CpuType::Amd => {
+			// A core going offline between snapshots must not abort the whole measurement
+			let mut msr_errors = Vec::new();
+			snapshot.cores = read_amd_core_counters(topology, online.as_ref())
+				.into_iter()
+				.map(|(_, energy)| energy?.map_err(|e| msr_errors.push(MsrReadError::from(&e))).ok())
+				.collect();
+			snapshot.msr_errors = msr_errors;
+		},
+		// Already rejected by `read_socket_snapshot`
+		CpuType::Unsupported => {},
+	}
+	snapshot.c0_counters = read_c0_counters();
+	snapshot.captured_at = Instant::now();
+	Ok(snapshot)
+}
+
+/// Where energy counters are read from.
+#[derive(Debug, Clone)]
+pub enum EnergySource {
+	/// The RAPL MSRs, counting in steps of `1 / 2^energy_unit` J
+	Msr { energy_unit: u64 },
+	/// The `power` perf PMU
+	Perf(PerfRapl),
+	/// The powercap sysfs zones, for when perf events cannot be read
+	Powercap(PowercapZones),
+}
+
+impl EnergySource {
+	/// Keeps the MSRs when they can be read, as only they have per-core readings, temperatures
+	/// and EPPs. Without them it tries the sources that need the fewest privileges first: perf
+	/// events, then powercap. The MSR error is returned if no source is usable.
+	pub fn detect(cpu_type: &CpuType) -> io::Result<Self> {
+		// Reading the energy unit once tells whether the MSRs are usable at all
+		let msr_error = match get_energy_unit(cpu_type) {
+			Ok(energy_unit) => return Ok(Self::Msr { energy_unit }),
+			Err(e) => e,
+		};
+		PerfRapl::open()
+			.map(Self::Perf)
+			.or_else(|_| PowercapZones::discover().map(Self::Powercap))
+			.map_err(|_| msr_error)
+	}
+
+	/// Where energy is read from, e.g. `perf events`.
+	#[must_use]
+	pub const fn name(&self) -> &'static str {
+		match self {
+			Self::Msr { .. } => "the RAPL MSRs",
+			Self::Perf(_) => "perf events",
+			Self::Powercap(_) => "powercap sysfs",
+		}
+	}
+
+	/// Says where energy is read from unless it is the MSRs, as the other sources lack per-core
+	/// readings, temperatures and EPPs.
+	#[must_use]
+	pub fn note(&self) -> Option<String> {
+		(!self.reads_msrs()).then(|| {
+			format!(
+				"Reading RAPL energy from {}, without per-core readings, temperatures or EPPs.",
+				self.name()
+			)
+		})
+	}
+
+	/// Whether the source reads the MSRs, and so has per-core readings, temperatures and EPPs.
+	#[must_use]
+	pub const fn reads_msrs(&self) -> bool {
+		matches!(self, Self::Msr { .. })
+	}
+}
+
+/// Measures the power of every domain over `duration`, with the core frequencies at its end and,
+/// when `source` reads the MSRs, the core temperatures and EPPs. Other sources cannot read them,
+/// so they are not tried on every sample.
+pub fn measure_power_sample(
+	cpu_type: &CpuType,
+	topology: &CpuTopology,
+	source: &EnergySource,
+	duration: Duration,
+) -> io::Result<PowerSample> {
+	let mut sample = match source {
+		EnergySource::Msr { energy_unit } => {
+			let initial_snapshot = read_energy_snapshot(cpu_type, topology)?;
+			thread::sleep(duration);
+			let final_snapshot = read_energy_snapshot(cpu_type, topology)?;
+			let counter_mask = get_energy_counter_mask(cpu_type);
+			final_snapshot.power_since(&initial_snapshot, *energy_unit, counter_mask)
+		},
+		EnergySource::Perf(events) => events.measure_power_sample(duration)?,
+		EnergySource::Powercap(zones) => zones.measure_power_sample(duration)?,
+	};
+	sample.frequencies = read_core_frequencies(topology);
+	if source.reads_msrs() {
+		sample.temperatures = read_core_temperatures(cpu_type, topology);
+		sample.energy_perf_preferences = read_core_energy_perf_preferences(cpu_type, topology);
+	}
+	sample.core_types = topology
+		.core_types
+		.iter()
+		.map(|(&core, &core_type)| (core, core_type))
+		.collect();
+	if let Ok(online) = read_online_cpus() {
+		sample.offline_cores = offline_cores(topology, &online);
+	}
+	Ok(sample)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Intel's usual energy unit of 2^-14 J (≈61 µJ) per count
+	const ENERGY_UNIT: u64 = 14;
+
+	fn socket_snapshot(socket: usize, package: u64, pp0: Option<u64>) -> EnergySnapshot {
+		EnergySnapshot {
+			package,
+			cores: Vec::new(),
+			pp0,
+			pp1: None,
+			dram: None,
+			platform: None,
+			sockets: vec![(socket, package)],
+			core_aperf: Vec::new(),
+			msr_errors: Vec::new(),
+			c0_counters: None,
+			captured_at: Instant::now(),
+		}
+	}
+
+	#[test]
+	fn merge_sums_sockets_and_drops_domains_one_lacks() {
+		let sockets = [socket_snapshot(0, 100, Some(10)), socket_snapshot(1, u64::MAX, None)];
+		let merged = EnergySnapshot::merge(&sockets);
+
+		assert_eq!(merged.package, 99);
+		assert_eq!(merged.sockets, vec![(0, 100), (1, u64::MAX)]);
+		assert_eq!(merged.pp0, None);
+		assert_eq!(merged.pp1, None);
+	}
+
+	#[test]
+	fn f64_power_keeps_the_precision_integer_power_truncates() {
+		// One count over 1 ms is exactly 61.03515625 mW
+		let exact_mw = 1_000_000.0 / (1u64 << ENERGY_UNIT) as f64;
+		let interval = Duration::from_millis(1);
+
+		let power_mw = calculate_power_mw_f64(0, 1, interval, ENERGY_UNIT, CounterWidth::Bits32.mask());
+		let power_uw = calculate_power_uw(0, 1, interval, ENERGY_UNIT, CounterWidth::Bits32);
+
+		assert!((power_mw - exact_mw).abs() < 1e-9);
+		assert_eq!(power_uw, 61_000);
+		assert!((power_uw as f64 / 1000.0 - exact_mw).abs() > (power_mw - exact_mw).abs());
+	}
+
+	#[test]
+	fn f64_and_integer_power_agree_on_whole_joules() {
+		// 10 J over 1 s
+		let counts = 10 << ENERGY_UNIT;
+		let interval = Duration::from_secs(1);
+
+		let power_mw = calculate_power_mw_f64(0, counts, interval, ENERGY_UNIT, CounterWidth::Bits32.mask());
+		let power_uw = calculate_power_uw(0, counts, interval, ENERGY_UNIT, CounterWidth::Bits32);
+
+		assert_eq!(power_uw, 10_000_000);
+		assert!((power_mw - 10_000.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn f64_power_handles_counter_wraparound() {
+		let mask = CounterWidth::Bits32.mask();
+		let counts = 1 << ENERGY_UNIT;
+		let interval = Duration::from_secs(1);
+
+		let power_mw = calculate_power_mw_f64(mask - counts + 1, 0, interval, ENERGY_UNIT, mask);
+
+		assert!((power_mw - 1000.0).abs() < 1e-9);
+	}
+}
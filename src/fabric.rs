@@ -0,0 +1,84 @@
+use crate::stats::pearson_correlation;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+
+const DRM_DEVICE_PATH: &str = "/sys/class/drm/card0/device";
+const CORRELATION_SAMPLES: usize = 20;
+
+/// Infinity Fabric and memory clocks of an AMD APU, as reported by amdgpu.
+#[derive(Debug, Clone, Copy)]
+pub struct FabricClocks {
+	pub fclk_mhz: u32,
+	pub mclk_mhz: Option<u32>,
+}
+
+impl FabricClocks {
+	/// FCLK below half of MCLK is an unusual ratio that adds memory latency.
+	#[must_use]
+	pub fn is_fclk_unusual(&self) -> bool {
+		self.mclk_mhz.is_some_and(|mclk| self.fclk_mhz * 2 < mclk)
+	}
+}
+
+/// Reads the active FCLK and MCLK levels from `pp_dpm_fclk` and `pp_dpm_mclk`. Only APUs driven
+/// by amdgpu expose them.
+#[must_use]
+pub fn read_fabric_clocks() -> Option<FabricClocks> {
+	Some(FabricClocks {
+		fclk_mhz: read_active_dpm_level(&format!("{DRM_DEVICE_PATH}/pp_dpm_fclk"))?,
+		mclk_mhz: read_active_dpm_level(&format!("{DRM_DEVICE_PATH}/pp_dpm_mclk")),
+	})
+}
+
+/// Parses the level marked with `*` in a DPM table such as `1: 1600Mhz *`.
+fn read_active_dpm_level(path: &str) -> Option<u32> {
+	let table = fs::read_to_string(path).ok()?;
+	let active = table.lines().find(|line| line.trim_end().ends_with('*'))?;
+	let (_, level) = active.split_once(':')?;
+	level
+		.trim()
+		.trim_end_matches('*')
+		.trim()
+		.trim_end_matches(|c: char| c.is_ascii_alphabetic())
+		.parse()
+		.ok()
+}
+
+/// Correlates FCLK changes with package power over the most recent updates.
+#[derive(Debug, Default)]
+pub struct FclkTracker {
+	samples: VecDeque<(f64, f64)>,
+}
+
+impl FclkTracker {
+	pub fn record(&mut self, fclk_mhz: u32, pkg_watts: f64) {
+		self.samples.push_back((f64::from(fclk_mhz), pkg_watts));
+		if self.samples.len() > CORRELATION_SAMPLES {
+			self.samples.pop_front();
+		}
+	}
+
+	/// Pearson correlation of FCLK and package power, `None` while either has not changed.
+	#[must_use]
+	pub fn correlation(&self) -> Option<f64> {
+		let (fclk, power): (Vec<f64>, Vec<f64>) = self.samples.iter().copied().unzip();
+		pearson_correlation(&fclk, &power)
+	}
+}
+
+/// Formats the clocks as `FCLK: 1600 MHz | MCLK: 3200 MHz | Power correlation: 0.82`.
+#[must_use]
+pub fn format_fabric_clocks(clocks: &FabricClocks, tracker: &FclkTracker) -> String {
+	let mut line = format!("FCLK: {} MHz", clocks.fclk_mhz);
+	if let Some(mclk) = clocks.mclk_mhz {
+		let _ = write!(line, " | MCLK: {mclk} MHz");
+	}
+	if let Some(correlation) = tracker.correlation() {
+		let _ = write!(line, " | Power correlation: {correlation:.2}");
+	}
+	if clocks.is_fclk_unusual() {
+		line.push_str(" [FCLK < MCLK/2]");
+	}
+	line
+}
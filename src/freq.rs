@@ -0,0 +1,32 @@
+use crate::topology::CpuTopology;
+use std::collections::HashMap;
+use std::{fs, io};
+
+const SYSFS_CPU_PATH: &str = "/sys/devices/system/cpu";
+
+/// Current frequency of `cpu_id` as last requested by cpufreq (`scaling_cur_freq`).
+pub fn read_core_freq_khz(cpu_id: usize) -> io::Result<u32> {
+	fs::read_to_string(format!("{SYSFS_CPU_PATH}/cpu{cpu_id}/cpufreq/scaling_cur_freq"))?
+		.trim()
+		.parse()
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Frequency of every physical core in MHz, averaged over its threads that report one. SMT
+/// siblings share a clock, but cpufreq samples each thread separately.
+#[must_use]
+pub fn read_core_frequencies(topology: &CpuTopology) -> HashMap<usize, u32> {
+	topology
+		.core_to_threads
+		.iter()
+		.filter_map(|(&core, threads)| {
+			let freqs_khz: Vec<u64> = threads
+				.iter()
+				.filter_map(|&thread_id| read_core_freq_khz(thread_id).ok())
+				.map(u64::from)
+				.collect();
+			let count = freqs_khz.len() as u64;
+			(count > 0).then(|| (core, (freqs_khz.iter().sum::<u64>() / count / 1000) as u32))
+		})
+		.collect()
+}
@@ -0,0 +1,79 @@
+use crate::topology::CpuTopology;
+use crate::{CpuType, HWP_PERF_MASK, INTEL_HWP_REQUEST_MSR, read_msr};
+use std::collections::HashMap;
+use std::io;
+
+/// CPUID leaf 6, EAX bit 7: HWP base registers
+const CPUID_HWP: u32 = 1 << 7;
+
+/// The performance range the OS asked for in `IA32_HWP_REQUEST`, in the abstract performance
+/// levels of [`HwpCapabilities`](crate::HwpCapabilities).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HwpRequest {
+	pub minimum_perf: u8,
+	pub maximum_perf: u8,
+	/// 0 leaves the choice to the hardware
+	pub desired_perf: u8,
+	/// Energy-performance preference (EPP), from 0 (performance) to 255 (energy saving)
+	pub energy_perf_preference: u8,
+}
+
+impl HwpRequest {
+	#[must_use]
+	pub fn from_msr(value: u64) -> Self {
+		Self {
+			minimum_perf: (value & HWP_PERF_MASK) as u8,
+			maximum_perf: ((value >> 8) & HWP_PERF_MASK) as u8,
+			desired_perf: ((value >> 16) & HWP_PERF_MASK) as u8,
+			energy_perf_preference: ((value >> 24) & HWP_PERF_MASK) as u8,
+		}
+	}
+}
+
+/// Name `intel_pstate` gives an EPP value in `energy_performance_preference`, if it is one of
+/// its presets.
+#[must_use]
+pub const fn epp_name(epp: u8) -> Option<&'static str> {
+	match epp {
+		0 => Some("performance"),
+		128 => Some("balance_performance"),
+		192 => Some("balance_power"),
+		255 => Some("power"),
+		_ => None,
+	}
+}
+
+/// Whether the CPU implements HWP, per CPUID leaf 6. The OS may still leave it disabled, in
+/// which case the request MSR is not used.
+#[must_use]
+pub fn hwp_supported() -> bool {
+	#[cfg(target_arch = "x86_64")]
+	{
+		#[allow(unused_unsafe)]
+		let leaf6 = unsafe { std::arch::x86_64::__cpuid(6) };
+		leaf6.eax & CPUID_HWP != 0
+	}
+	#[cfg(not(target_arch = "x86_64"))]
+	false
+}
+
+pub fn read_hwp_request(cpu_id: usize) -> io::Result<HwpRequest> {
+	Ok(HwpRequest::from_msr(read_msr(INTEL_HWP_REQUEST_MSR, cpu_id)?))
+}
+
+/// EPP of every Intel core with HWP, read through its first thread. Cores whose request cannot be
+/// read are left out, and the map is empty without HWP.
+#[must_use]
+pub fn read_core_energy_perf_preferences(cpu_type: &CpuType, topology: &CpuTopology) -> HashMap<usize, u8> {
+	if !matches!(cpu_type, CpuType::Intel) || !hwp_supported() {
+		return HashMap::new();
+	}
+	topology
+		.core_to_threads
+		.iter()
+		.filter_map(|(&core, threads)| {
+			let request = read_hwp_request(*threads.first()?).ok()?;
+			Some((core, request.energy_perf_preference))
+		})
+		.collect()
+}
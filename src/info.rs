@@ -0,0 +1,367 @@
+use crate::powercap::enumerate_rapl_domains;
+use crate::topology::CpuTopology;
+use crate::{
+	AMD_ENERGY_CORE_MSR, AMD_ENERGY_PKG_MSR, CpuType, INTEL_CORE_ENERGY_MSR, INTEL_DRAM_ENERGY_MSR,
+	INTEL_PKG_ENERGY_MSR, INTEL_PKG_POWER_INFO_MSR, INTEL_PKG_POWER_LIMIT_MSR, INTEL_POWER_UNIT_MSR,
+	INTEL_PP1_ENERGY_MSR, INTEL_PSYS_ENERGY_MSR, PL2_SHIFT, POWER_LIMIT_MASK, POWER_UNIT_MASK, read_msr,
+};
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::process::Command;
+
+const POWERCAP_PKG_LIMIT_PATH: &str = "/sys/class/powercap/intel-rapl/intel-rapl:0/constraint_0_power_limit_uw";
+
+/// Intel package power limits from `MSR_PKG_POWER_LIMIT`.
+#[derive(Debug, Clone, Copy)]
+pub struct RaplLimits {
+	/// Long-term (sustained) limit in watts
+	pub pl1_watts: f64,
+	/// Short-term (burst) limit in watts
+	pub pl2_watts: f64,
+}
+
+/// Thermal design power and where it was found.
+#[derive(Debug, Clone, Copy)]
+pub struct TdpSource {
+	pub value_uw: u64,
+	pub source: &'static str,
+}
+
+impl TdpSource {
+	pub fn watts(&self) -> f64 {
+		self.value_uw as f64 / 1_000_000.0
+	}
+}
+
+/// Looks up the TDP in `MSR_PKG_POWER_INFO` (Intel), the powercap package limit (also exposed
+/// for AMD by newer kernels) and `dmidecode`, falling back to `user_tdp_watts`.
+pub fn detect_tdp(cpu_type: &CpuType, user_tdp_watts: Option<f64>) -> Option<TdpSource> {
+	let to_uw = |watts: f64| (watts * 1_000_000.0) as u64;
+	let from_msr = || {
+		matches!(cpu_type, CpuType::Intel)
+			.then(read_intel_tdp)
+			.flatten()
+			.filter(|&tdp| tdp > 0.0)
+			.map(|tdp| TdpSource {
+				value_uw: to_uw(tdp),
+				source: "MSR_PKG_POWER_INFO",
+			})
+	};
+	let from_powercap = || {
+		fs::read_to_string(POWERCAP_PKG_LIMIT_PATH)
+			.ok()?
+			.trim()
+			.parse()
+			.ok()
+			.filter(|&value_uw: &u64| value_uw > 0)
+			.map(|value_uw| TdpSource {
+				value_uw,
+				source: "powercap",
+			})
+	};
+	let from_dmidecode = || {
+		read_dmidecode_tdp().map(|tdp| TdpSource {
+			value_uw: to_uw(tdp),
+			source: "dmidecode",
+		})
+	};
+	let from_user = || {
+		user_tdp_watts.map(|tdp| TdpSource {
+			value_uw: to_uw(tdp),
+			source: "--tdp-watts",
+		})
+	};
+
+	from_msr()
+		.or_else(from_powercap)
+		.or_else(from_dmidecode)
+		.or_else(from_user)
+}
+
+/// Few BIOSes list the TDP in the processor table, so this only finds a `TDP: 65 W` style line.
+fn read_dmidecode_tdp() -> Option<f64> {
+	let output = Command::new("dmidecode").args(["-t", "processor"]).output().ok()?;
+	String::from_utf8_lossy(&output.stdout).lines().find_map(|line| {
+		let (key, value) = line.split_once(':')?;
+		if !key.trim().eq_ignore_ascii_case("TDP") {
+			return None;
+		}
+		value.trim().trim_end_matches('W').trim().parse().ok()
+	})
+}
+
+/// Summary of the detected hardware and which RAPL features can be read on it.
+#[derive(Debug, Clone)]
+pub struct HardwareInfo {
+	pub cpu_type: CpuType,
+	pub model_name: String,
+	pub family: Option<u32>,
+	pub model: Option<u32>,
+	pub physical_cores: usize,
+	pub logical_cpus: usize,
+	pub threads_per_core: usize,
+	pub smt_uniform: bool,
+	pub rapl_domains: Vec<&'static str>,
+	/// RAPL zones the kernel exposes through powercap
+	pub kernel_rapl_domains: Vec<String>,
+	/// Energy status unit exponent: one counter step is `1 / 2^unit` J
+	pub energy_unit: Option<u64>,
+	pub power_limits: Option<RaplLimits>,
+	pub tdp: Option<TdpSource>,
+}
+
+impl HardwareInfo {
+	pub fn collect(cpu_type: CpuType, user_tdp_watts: Option<f64>) -> Self {
+		let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+		let field = |name: &str| {
+			cpuinfo
+				.lines()
+				.find(|line| line.split(':').next().is_some_and(|key| key.trim() == name))
+				.and_then(|line| line.split_once(':'))
+				.map(|(_, value)| value.trim().to_string())
+		};
+
+		let topology = CpuTopology::detect();
+		let rapl_domains = probe_rapl_domains(&cpu_type);
+		let kernel_rapl_domains = enumerate_rapl_domains()
+			.map(|domains| domains.into_keys().collect())
+			.unwrap_or_default();
+		let energy_unit = crate::get_energy_unit(&cpu_type).ok();
+		let power_limits = read_rapl_limits(&cpu_type);
+		let tdp = detect_tdp(&cpu_type, user_tdp_watts);
+
+		Self {
+			model_name: field("model name").unwrap_or_else(|| String::from("Unknown")),
+			family: field("cpu family").and_then(|value| value.parse().ok()),
+			model: field("model").and_then(|value| value.parse().ok()),
+			physical_cores: topology.physical_cores(),
+			logical_cpus: topology.core_to_threads.values().map(Vec::len).sum(),
+			threads_per_core: topology.threads_per_core(),
+			smt_uniform: topology.is_smt_uniform(),
+			rapl_domains,
+			kernel_rapl_domains,
+			energy_unit,
+			power_limits,
+			tdp,
+			cpu_type,
+		}
+	}
+
+	/// MSR domains the kernel does not expose, and kernel zones of a known domain whose MSR could
+	/// not be read. Empty when either list is, since then there is nothing to compare.
+	#[must_use]
+	pub fn rapl_domain_mismatches(&self) -> Vec<String> {
+		if self.rapl_domains.is_empty() || self.kernel_rapl_domains.is_empty() {
+			return Vec::new();
+		}
+		// `package-0/core` is a `core` zone
+		let kernel_zones: Vec<&str> = self
+			.kernel_rapl_domains
+			.iter()
+			.map(|name| {
+				let zone = name.rsplit('/').next().unwrap_or(name);
+				if zone.starts_with("package-") { "package" } else { zone }
+			})
+			.collect();
+
+		let mut mismatches = Vec::new();
+		for &(msr_domain, kernel_zone) in KERNEL_ZONE_NAMES {
+			let msr_readable = self.rapl_domains.contains(&msr_domain);
+			let kernel_exposed = kernel_zones.contains(&kernel_zone);
+			if msr_readable && !kernel_exposed {
+				mismatches.push(format!(
+					"{msr_domain} is readable through its MSR, but the kernel exposes no {kernel_zone} zone"
+				));
+			} else if kernel_exposed && !msr_readable && self.probed_domain(msr_domain) {
+				mismatches.push(format!(
+					"the kernel exposes a {kernel_zone} zone, but the {msr_domain} MSR cannot be read"
+				));
+			}
+		}
+		mismatches
+	}
+
+	/// Whether `probe_rapl_domains` checks `msr_domain` on this CPU type.
+	fn probed_domain(&self, msr_domain: &str) -> bool {
+		match self.cpu_type {
+			CpuType::Intel => INTEL_RAPL_DOMAINS.iter().any(|&(name, _)| name == msr_domain),
+			CpuType::Amd => AMD_RAPL_DOMAINS.iter().any(|&(name, _)| name == msr_domain),
+			CpuType::Unsupported => false,
+		}
+	}
+
+	pub fn to_json(&self) -> String {
+		let optional = |value: Option<String>| value.unwrap_or_else(|| String::from("null"));
+		let domains: Vec<String> = self.rapl_domains.iter().map(|domain| escape_json(domain)).collect();
+
+		let mut json = String::from("{");
+		let _ = write!(json, "\"cpu_type\":{}", escape_json(&format!("{:?}", self.cpu_type)));
+		let _ = write!(json, ",\"model_name\":{}", escape_json(&self.model_name));
+		let _ = write!(json, ",\"family\":{}", optional(self.family.map(|v| v.to_string())));
+		let _ = write!(json, ",\"model\":{}", optional(self.model.map(|v| v.to_string())));
+		let _ = write!(json, ",\"physical_cores\":{}", self.physical_cores);
+		let _ = write!(json, ",\"logical_cpus\":{}", self.logical_cpus);
+		let _ = write!(json, ",\"threads_per_core\":{}", self.threads_per_core);
+		let _ = write!(json, ",\"smt_uniform\":{}", self.smt_uniform);
+		let _ = write!(json, ",\"rapl_domains\":[{}]", domains.join(","));
+		let kernel_domains: Vec<String> = self
+			.kernel_rapl_domains
+			.iter()
+			.map(|domain| escape_json(domain))
+			.collect();
+		let _ = write!(json, ",\"kernel_rapl_domains\":[{}]", kernel_domains.join(","));
+		let _ = write!(
+			json,
+			",\"energy_unit\":{}",
+			optional(self.energy_unit.map(|v| v.to_string()))
+		);
+		let _ = write!(
+			json,
+			",\"pl1_watts\":{}",
+			optional(self.power_limits.map(|limits| limits.pl1_watts.to_string()))
+		);
+		let _ = write!(
+			json,
+			",\"pl2_watts\":{}",
+			optional(self.power_limits.map(|limits| limits.pl2_watts.to_string()))
+		);
+		let _ = write!(
+			json,
+			",\"tdp_watts\":{}",
+			optional(self.tdp.map(|tdp| tdp.watts().to_string()))
+		);
+		let _ = write!(
+			json,
+			",\"tdp_source\":{}",
+			optional(self.tdp.map(|tdp| escape_json(tdp.source)))
+		);
+		json.push('}');
+		json
+	}
+}
+
+impl fmt::Display for HardwareInfo {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let unknown = || String::from("unknown");
+		writeln!(f, "CPU:              {} ({:?})", self.model_name, self.cpu_type)?;
+		writeln!(
+			f,
+			"Family/model:     {}/{}",
+			self.family.map_or_else(unknown, |v| v.to_string()),
+			self.model.map_or_else(unknown, |v| v.to_string())
+		)?;
+		writeln!(f, "Physical cores:   {}", self.physical_cores)?;
+		writeln!(f, "Logical CPUs:     {}", self.logical_cpus)?;
+		writeln!(
+			f,
+			"SMT:              {} thread(s) per core{}",
+			self.threads_per_core,
+			if self.smt_uniform { "" } else { " (non-uniform)" }
+		)?;
+		writeln!(f, "RAPL domains:     {}", self.rapl_domains.join(", "))?;
+		if !self.kernel_rapl_domains.is_empty() {
+			writeln!(f, "Kernel RAPL:      {}", self.kernel_rapl_domains.join(", "))?;
+		}
+		for mismatch in self.rapl_domain_mismatches() {
+			writeln!(f, "Warning:          {mismatch}")?;
+		}
+		writeln!(
+			f,
+			"Energy unit:      {}",
+			self.energy_unit.map_or_else(unknown, |unit| format!(
+				"{unit} ({:.2} µJ per count)",
+				1_000_000.0 / f64::from(1u32 << unit)
+			))
+		)?;
+		writeln!(
+			f,
+			"Power limits:     {}",
+			self.power_limits.map_or_else(unknown, |limits| format!(
+				"PL1 {:.1} W, PL2 {:.1} W",
+				limits.pl1_watts, limits.pl2_watts
+			))
+		)?;
+		write!(
+			f,
+			"TDP:              {}",
+			self.tdp
+				.map_or_else(unknown, |tdp| format!("{:.1} W (from {})", tdp.watts(), tdp.source))
+		)
+	}
+}
+
+const INTEL_RAPL_DOMAINS: &[(&str, u32)] = &[
+	("package", INTEL_PKG_ENERGY_MSR),
+	("pp0", INTEL_CORE_ENERGY_MSR),
+	("pp1", INTEL_PP1_ENERGY_MSR),
+	("dram", INTEL_DRAM_ENERGY_MSR),
+	("psys", INTEL_PSYS_ENERGY_MSR),
+];
+const AMD_RAPL_DOMAINS: &[(&str, u32)] = &[("package", AMD_ENERGY_PKG_MSR), ("core", AMD_ENERGY_CORE_MSR)];
+
+/// Powercap zone name of each MSR domain
+const KERNEL_ZONE_NAMES: &[(&str, &str)] = &[
+	("package", "package"),
+	("pp0", "core"),
+	("core", "core"),
+	("pp1", "uncore"),
+	("dram", "dram"),
+	("psys", "psys"),
+];
+
+fn probe_rapl_domains(cpu_type: &CpuType) -> Vec<&'static str> {
+	let domains = match cpu_type {
+		CpuType::Intel => INTEL_RAPL_DOMAINS,
+		CpuType::Amd => AMD_RAPL_DOMAINS,
+		CpuType::Unsupported => &[],
+	};
+
+	domains
+		.iter()
+		.filter(|(_, msr_address)| read_msr(*msr_address, 0).is_ok())
+		.map(|(name, _)| *name)
+		.collect()
+}
+
+/// Watts per power limit count, from bits 3:0 of the power unit MSR.
+fn read_intel_power_unit_watts() -> Option<f64> {
+	let unit_msr = read_msr(INTEL_POWER_UNIT_MSR, 0).ok()?;
+	Some(1.0 / f64::from(1u32 << (unit_msr & POWER_UNIT_MASK)))
+}
+
+/// Reads the package power limits. Only Intel exposes them through an MSR.
+pub fn read_rapl_limits(cpu_type: &CpuType) -> Option<RaplLimits> {
+	if !matches!(cpu_type, CpuType::Intel) {
+		return None;
+	}
+	let power_unit = read_intel_power_unit_watts()?;
+	let limit_msr = read_msr(INTEL_PKG_POWER_LIMIT_MSR, 0).ok()?;
+	Some(RaplLimits {
+		pl1_watts: (limit_msr & POWER_LIMIT_MASK) as f64 * power_unit,
+		pl2_watts: ((limit_msr >> PL2_SHIFT) & POWER_LIMIT_MASK) as f64 * power_unit,
+	})
+}
+
+fn read_intel_tdp() -> Option<f64> {
+	let power_unit = read_intel_power_unit_watts()?;
+	let info_msr = read_msr(INTEL_PKG_POWER_INFO_MSR, 0).ok()?;
+	Some((info_msr & POWER_LIMIT_MASK) as f64 * power_unit)
+}
+
+/// Quotes and escapes a string for use as a JSON value.
+pub fn escape_json(value: &str) -> String {
+	let mut escaped = String::with_capacity(value.len() + 2);
+	escaped.push('"');
+	for c in value.chars() {
+		match c {
+			'"' => escaped.push_str("\\\""),
+			'\\' => escaped.push_str("\\\\"),
+			c if c.is_control() => {
+				let _ = write!(escaped, "\\u{:04x}", c as u32);
+			},
+			c => escaped.push(c),
+		}
+	}
+	escaped.push('"');
+	escaped
+}
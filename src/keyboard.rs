@@ -0,0 +1,84 @@
+use crate::monitor::{MonitorState, PowerMonitor};
+use std::fs::File;
+use std::io::{self, Read};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+const TTY_PATH: &str = "/dev/tty";
+const CTRL_C: u8 = 0x03;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCommand {
+	TogglePause,
+	/// Clears the averages, history and session totals
+	ResetStatistics,
+	Quit,
+}
+
+impl KeyCommand {
+	pub fn apply(self, monitor: &mut PowerMonitor) -> io::Result<()> {
+		match self {
+			Self::TogglePause if monitor.state() == MonitorState::Paused => monitor.resume()?,
+			Self::TogglePause => monitor.pause()?,
+			Self::ResetStatistics => monitor.reset_statistics(),
+			Self::Quit => monitor.stop(),
+		}
+		Ok(())
+	}
+}
+
+/// Puts the terminal into unbuffered, non-echoing input mode until dropped. Signal keys are
+/// disabled too, so Ctrl+C arrives as a key press and the terminal is always restored.
+pub struct RawTerminal {
+	saved_settings: String,
+}
+
+impl RawTerminal {
+	pub fn enable() -> io::Result<Self> {
+		let output = stty(&["-g"])?;
+		let saved_settings = String::from_utf8_lossy(&output).trim().to_string();
+		stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+		Ok(Self { saved_settings })
+	}
+}
+
+impl Drop for RawTerminal {
+	fn drop(&mut self) {
+		let _ = stty(&[self.saved_settings.as_str()]);
+	}
+}
+
+fn stty(args: &[&str]) -> io::Result<Vec<u8>> {
+	let output = Command::new("stty")
+		.args(args)
+		.stdin(File::open(TTY_PATH)?)
+		.stderr(Stdio::null())
+		.output()?;
+	if !output.status.success() {
+		return Err(io::Error::other(format!("stty {} failed", args.join(" "))));
+	}
+	Ok(output.stdout)
+}
+
+/// Reads key presses from the terminal on a background thread: `p` toggles pause, `c` resets the
+/// statistics, `q` and Ctrl+C quit. Other keys are ignored.
+pub fn spawn_key_reader() -> io::Result<Receiver<KeyCommand>> {
+	let mut tty = File::open(TTY_PATH)?;
+	let (sender, receiver) = mpsc::channel();
+	thread::spawn(move || {
+		let mut key = [0u8; 1];
+		while tty.read_exact(&mut key).is_ok() {
+			let command = match key[0] {
+				b'p' | b'P' => KeyCommand::TogglePause,
+				b'c' | b'C' => KeyCommand::ResetStatistics,
+				b'q' | b'Q' | CTRL_C => KeyCommand::Quit,
+				_ => continue,
+			};
+			if sender.send(command).is_err() {
+				break;
+			}
+		}
+	});
+	Ok(receiver)
+}
@@ -0,0 +1,744 @@
+#[cfg(feature = "async")]
+pub mod async_monitor;
+pub mod bench;
+pub mod cgroup;
+pub mod daemon;
+pub mod display;
+pub mod energy;
+pub mod fabric;
+pub mod freq;
+pub mod hwp;
+pub mod info;
+pub mod keyboard;
+pub mod monitor;
+pub mod online;
+pub mod output;
+pub mod perf;
+pub mod power;
+pub mod powercap;
+pub mod profile;
+#[cfg(feature = "serde")]
+pub mod replay;
+pub mod signal;
+pub mod stats;
+pub mod thermal;
+pub mod topology;
+#[cfg(feature = "tui")]
+pub mod tui;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+use std::{fmt, fs, io, thread};
+
+// AMD RAPL MSR addresses
+/// `MSR_RAPL_PWR_UNIT`, same layout as `INTEL_POWER_UNIT_MSR`.
+pub const AMD_ENERGY_UNIT_MSR: u32 = 0xC001_0299;
+/// `MSR_CORE_ENERGY_STAT`, per core: bits 31:0 count energy in units of `1 / 2^ESU` J.
+pub const AMD_ENERGY_CORE_MSR: u32 = 0xC001_029A;
+/// `MSR_PKG_ENERGY_STAT`: bits 31:0 count energy in units of `1 / 2^ESU` J.
+pub const AMD_ENERGY_PKG_MSR: u32 = 0xC001_029B;
+
+// Intel RAPL MSR addresses
+/// `MSR_RAPL_POWER_UNIT`: power unit in bits 3:0 (`1 / 2^PU` W), energy status unit (ESU) in
+/// bits 12:8 (`1 / 2^ESU` J) and time unit in bits 19:16 (`1 / 2^TU` s).
+pub const INTEL_POWER_UNIT_MSR: u32 = 0x606;
+/// `MSR_PKG_ENERGY_STATUS`: bits 31:0 are the package energy counter, wrapping at 2^32.
+/// `energy_uj = raw_count * 1_000_000 / 2^ESU`, with ESU from `INTEL_POWER_UNIT_MSR` bits 12:8.
+pub const INTEL_PKG_ENERGY_MSR: u32 = 0x611;
+/// `MSR_PP0_ENERGY_STATUS`: energy of all cores in bits 31:0, same units as the package counter.
+pub const INTEL_CORE_ENERGY_MSR: u32 = 0x639;
+/// `MSR_PP1_ENERGY_STATUS`: energy of the uncore device, usually the integrated GPU, in bits 31:0.
+pub const INTEL_PP1_ENERGY_MSR: u32 = 0x641;
+/// `MSR_DRAM_ENERGY_STATUS`: DRAM energy in bits 31:0. Some server parts use a fixed 15.3 µJ unit
+/// instead of the ESU.
+pub const INTEL_DRAM_ENERGY_MSR: u32 = 0x619;
+/// `MSR_DRAM_POWER_INFO`: DRAM thermal spec power in bits 14:0, minimum in 30:16 and maximum in
+/// 46:32, each `raw * 1 / 2^PU` W.
+pub const INTEL_DRAM_POWER_INFO_MSR: u32 = 0x61C;
+/// `MSR_PLATFORM_ENERGY_STATUS`: whole-platform (PSys) energy in bits 31:0.
+pub const INTEL_PSYS_ENERGY_MSR: u32 = 0x64D;
+/// `MSR_PKG_POWER_LIMIT`: PL1 in bits 14:0 and PL2 in bits 46:32, each `raw * 1 / 2^PU` W.
+pub const INTEL_PKG_POWER_LIMIT_MSR: u32 = 0x610;
+/// `MSR_PKG_POWER_INFO`: thermal spec power (TDP) in bits 14:0, `raw * 1 / 2^PU` W.
+pub const INTEL_PKG_POWER_INFO_MSR: u32 = 0x614;
+/// `IA32_THERM_STATUS`: per-core thermal status, with the digital readout in degrees below TjMax
+/// in bits 22:16, valid while bit 31 is set.
+pub const IA32_THERM_STATUS_MSR: u32 = 0x19C;
+/// `MSR_TEMPERATURE_TARGET`: TjMax in °C in bits 23:16.
+pub const INTEL_TEMPERATURE_TARGET_MSR: u32 = 0x1A2;
+/// `IA32_HWP_CAPABILITIES`: highest performance in bits 7:0, guaranteed in 15:8, most efficient
+/// in 23:16 and lowest in 31:24, all in abstract performance levels.
+pub const INTEL_HWP_CAPABILITIES_MSR: u32 = 0x771;
+/// `IA32_HWP_REQUEST`: per-CPU minimum, maximum and desired performance and energy preference.
+pub const INTEL_HWP_REQUEST_MSR: u32 = 0x774;
+/// `IA32_ENERGY_PERF_BIAS`: bits 3:0, from 0 (performance) to 15 (energy saving).
+pub const INTEL_ENERGY_PERF_BIAS_MSR: u32 = 0x1B0;
+
+// Architectural counters, available on both vendors
+/// `IA32_TSC`: time stamp counter, ticking at a constant rate in every C-state.
+pub const IA32_TSC_MSR: u32 = 0x10;
+/// `IA32_MPERF`: ticks at the TSC rate, but only while the CPU is in C0.
+pub const IA32_MPERF_MSR: u32 = 0xE7;
+/// `IA32_APERF`: counts the cycles actually executed in C0, so it also scales with frequency.
+pub const IA32_APERF_MSR: u32 = 0xE8;
+
+// Field layouts of the RAPL MSRs
+pub const POWER_UNIT_MASK: u64 = 0xF;
+pub const ENERGY_STATUS_UNIT_SHIFT: u64 = 8;
+pub const ENERGY_STATUS_UNIT_MASK: u64 = 0x1F;
+pub const POWER_LIMIT_MASK: u64 = 0x7FFF;
+pub const PL2_SHIFT: u64 = 32;
+pub const HWP_PERF_MASK: u64 = 0xFF;
+
+const _: () = assert!(POWER_UNIT_MASK.trailing_ones() == 4 && POWER_UNIT_MASK.count_ones() == 4);
+const _: () = assert!(ENERGY_STATUS_UNIT_MASK.trailing_ones() == 5 && ENERGY_STATUS_UNIT_MASK.count_ones() == 5);
+const _: () = assert!(POWER_LIMIT_MASK.trailing_ones() == 15 && POWER_LIMIT_MASK.count_ones() == 15);
+const _: () = assert!(HWP_PERF_MASK.trailing_ones() == 8 && HWP_PERF_MASK.count_ones() == 8);
+// PL2 (bits 46:32) must not overlap PL1 (bits 14:0)
+const _: () = assert!((POWER_LIMIT_MASK << PL2_SHIFT) & POWER_LIMIT_MASK == 0);
+
+/// The only MSRs `write_msr` accepts. Writing anything else can hang or damage the machine.
+pub const WRITABLE_MSRS: &[(u32, &str)] = &[
+	(INTEL_PKG_POWER_LIMIT_MSR, "MSR_PKG_POWER_LIMIT"),
+	(INTEL_HWP_REQUEST_MSR, "MSR_HWP_REQUEST"),
+	(INTEL_ENERGY_PERF_BIAS_MSR, "MSR_ENERGY_PERF_BIAS"),
+];
+
+/// Bits 4:0 of the AMD unit MSR, checked as an alternative energy unit by
+/// [`validate_amd_energy_unit`]
+const AMD_ALT_ENERGY_UNIT_MASK: u64 = 0x1F;
+/// How long [`validate_amd_energy_unit`] counts package energy for, long enough for the counter to
+/// advance many steps even at idle
+const ENERGY_UNIT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// Package power above which an energy unit is taken to be decoded wrong
+const MAX_PLAUSIBLE_PACKAGE_WATTS: f64 = 1000.0;
+
+/// Plausible energy unit exponents per vendor as `(cpu type, min, max)`. Intel usually reports 14
+/// (≈61 µJ per count) and Zen 16 (≈15.3 µJ per count). The 3 in the low bits of the AMD unit MSR
+/// is the power unit, not the energy unit.
+pub const EXPECTED_ENERGY_UNITS: &[(CpuType, u64, u64)] = &[(CpuType::Intel, 10, 16), (CpuType::Amd, 14, 18)];
+
+pub const DATA_COLLECTION_INTERVAL_MS: u64 = 100;
+pub const DISPLAY_UPDATE_INTERVAL_MS: u64 = 200;
+/// Default number of samples averaged into each reading, overridable with `--window`
+pub const AVERAGING_ITERATIONS: usize = 10;
+/// Shortest sampling interval, below which the reads themselves dominate the measured interval
+pub const MIN_COLLECTION_INTERVAL_MS: u64 = 10;
+/// How often a monitoring session re-reads the topology to pick up hotplugged CPUs
+pub const TOPOLOGY_REFRESH_INTERVAL_MS: u64 = 5000;
+pub const POWER_SCALE: u64 = 1_000_000;
+/// Confidence of readings taken under a hypervisor, whose RAPL counters may reflect the host or
+/// be synthetic
+pub const VM_RAPL_CONFIDENCE: f64 = 0.3;
+
+/// Sampling and display timing of a monitoring session. The default uses the constants above.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonitorConfig {
+	/// Length of each power sample, at least `MIN_COLLECTION_INTERVAL_MS`
+	pub collection_interval_ms: u64,
+	pub display_interval_ms: u64,
+	/// Number of samples averaged into each reading
+	pub averaging_window: usize,
+	/// How often to check for hotplugged CPUs, never if `None`
+	pub topology_refresh_interval_ms: Option<u64>,
+	/// How far readings can be trusted, from 0 to 1, see [`PowerReading::confidence`]
+	///
+	/// [`PowerReading::confidence`]: crate::power::PowerReading::confidence
+	pub confidence: f64,
+}
+
+impl Default for MonitorConfig {
+	fn default() -> Self {
+		Self {
+			collection_interval_ms: DATA_COLLECTION_INTERVAL_MS,
+			display_interval_ms: DISPLAY_UPDATE_INTERVAL_MS,
+			averaging_window: AVERAGING_ITERATIONS,
+			topology_refresh_interval_ms: Some(TOPOLOGY_REFRESH_INTERVAL_MS),
+			confidence: 1.0,
+		}
+	}
+}
+
+impl MonitorConfig {
+	#[must_use]
+	pub const fn collection_interval(&self) -> Duration {
+		Duration::from_millis(self.collection_interval_ms)
+	}
+
+	#[must_use]
+	pub const fn display_interval(&self) -> Duration {
+		Duration::from_millis(self.display_interval_ms)
+	}
+
+	#[must_use]
+	pub fn topology_refresh_interval(&self) -> Option<Duration> {
+		self.topology_refresh_interval_ms.map(Duration::from_millis)
+	}
+}
+
+pub const MSR_LATENCY_SAMPLES: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CpuType {
+	Intel,
+	Amd,
+	Unsupported,
+}
+
+impl CpuType {
+	#[must_use]
+	pub const fn as_str(&self) -> &'static str {
+		match self {
+			Self::Intel => "Intel",
+			Self::Amd => "AMD",
+			Self::Unsupported => "Unsupported",
+		}
+	}
+}
+
+impl FromStr for CpuType {
+	type Err = CpuTypeParseError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"Intel" | "intel" => Ok(Self::Intel),
+			"AMD" | "amd" => Ok(Self::Amd),
+			"Unsupported" | "unknown" => Ok(Self::Unsupported),
+			_ => Err(CpuTypeParseError(s.to_string())),
+		}
+	}
+}
+
+/// A CPU type name other than `Intel`, `AMD` or `Unsupported`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuTypeParseError(pub String);
+
+impl fmt::Display for CpuTypeParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Unknown CPU type: {}", self.0)
+	}
+}
+
+impl Error for CpuTypeParseError {}
+
+/// Kind of a core on a hybrid CPU, such as the P-cores and E-cores of Alder Lake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CoreType {
+	Performance,
+	Efficiency,
+	/// Efficiency core of the SoC tile, without an L3 cache, as on Meteor Lake
+	LowPowerEfficiency,
+	/// Core recorded without a type in a replay or configuration file. Detection never yields it,
+	/// it leaves cores of unknown type out instead.
+	Unknown,
+}
+
+impl CoreType {
+	#[must_use]
+	pub const fn as_str(&self) -> &'static str {
+		match self {
+			Self::Performance => "P-core",
+			Self::Efficiency => "E-core",
+			Self::LowPowerEfficiency => "LP E-core",
+			Self::Unknown => "Unknown",
+		}
+	}
+}
+
+impl FromStr for CoreType {
+	type Err = CoreTypeParseError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"P-core" | "pcore" => Ok(Self::Performance),
+			"E-core" | "ecore" => Ok(Self::Efficiency),
+			"LP E-core" | "LP-E" | "lpe" => Ok(Self::LowPowerEfficiency),
+			"Unknown" | "unknown" => Ok(Self::Unknown),
+			_ => Err(CoreTypeParseError(s.to_string())),
+		}
+	}
+}
+
+/// A core type name other than `P-core`, `pcore`, `E-core`, `ecore`, `LP E-core`, `LP-E`, `lpe`,
+/// `Unknown` or `unknown`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreTypeParseError(pub String);
+
+impl fmt::Display for CoreTypeParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Unknown core type: {}", self.0)
+	}
+}
+
+impl Error for CoreTypeParseError {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HwpCapabilities {
+	pub max_perf: u8,
+	pub guaranteed_perf: u8,
+	pub lowest_perf: u8,
+}
+
+impl HwpCapabilities {
+	#[must_use]
+	pub fn from_msr(value: u64) -> Self {
+		Self {
+			max_perf: (value & HWP_PERF_MASK) as u8,
+			guaranteed_perf: ((value >> 8) & HWP_PERF_MASK) as u8,
+			lowest_perf: ((value >> 24) & HWP_PERF_MASK) as u8,
+		}
+	}
+
+	/// How far the highest performance level currently reported by the hardware is above the
+	/// guaranteed one.
+	#[must_use]
+	pub fn boost_headroom_percent(&self) -> f64 {
+		if self.guaranteed_perf == 0 {
+			return 0.0;
+		}
+		f64::from(self.max_perf.saturating_sub(self.guaranteed_perf)) / f64::from(self.guaranteed_perf) * 100.0
+	}
+}
+
+#[must_use]
+pub fn detect_cpu_type() -> CpuType {
+	let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+	if cpuinfo.contains("GenuineIntel") {
+		CpuType::Intel
+	} else if cpuinfo.contains("AuthenticAMD") {
+		CpuType::Amd
+	} else {
+		CpuType::Unsupported
+	}
+}
+
+/// Detects a hypervisor through the CPUID hypervisor-present bit or `/sys/hypervisor/type`.
+#[must_use]
+pub fn running_in_vm() -> bool {
+	#[cfg(target_arch = "x86_64")]
+	{
+		// CPUID leaf 1, ECX bit 31: hypervisor present
+		#[allow(unused_unsafe)]
+		let leaf1 = unsafe { std::arch::x86_64::__cpuid(1) };
+		if leaf1.ecx & (1 << 31) != 0 {
+			return true;
+		}
+	}
+
+	fs::read_to_string("/sys/hypervisor/type").is_ok_and(|hypervisor| !hypervisor.trim().is_empty())
+}
+
+/// Why an MSR could not be read, classified by errno so the user can be told how to fix it.
+#[derive(Debug)]
+pub enum MsrError {
+	/// `/dev/cpu/N/msr` needs root or `CAP_SYS_RAWIO` (EACCES, EPERM)
+	PermissionDenied,
+	/// The CPU does not implement the MSR (ENODEV, or EIO from the faulting `rdmsr`)
+	NotFound,
+	/// `/dev/cpu/N/msr` does not exist because the `msr` module is not loaded (ENOENT)
+	ModuleNotLoaded,
+	IoError(io::Error),
+}
+
+impl From<io::Error> for MsrError {
+	fn from(error: io::Error) -> Self {
+		match error.raw_os_error() {
+			Some(libc::EACCES | libc::EPERM) => Self::PermissionDenied,
+			Some(libc::ENODEV | libc::EIO) => Self::NotFound,
+			Some(libc::ENOENT) => Self::ModuleNotLoaded,
+			_ => Self::IoError(error),
+		}
+	}
+}
+
+impl fmt::Display for MsrError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::PermissionDenied => write!(
+				f,
+				"Permission denied reading MSRs: run as root or grant CAP_SYS_RAWIO, or make the powercap \
+				 energy_uj files readable to use them instead"
+			),
+			Self::NotFound => write!(f, "The CPU does not support this MSR"),
+			Self::ModuleNotLoaded => write!(
+				f,
+				"/dev/cpu/*/msr not found: run `modprobe msr`, or make the powercap energy_uj files readable to \
+				 use them instead"
+			),
+			Self::IoError(e) => write!(f, "MSR read failed: {e}"),
+		}
+	}
+}
+
+impl Error for MsrError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::IoError(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<MsrError> for io::Error {
+	fn from(error: MsrError) -> Self {
+		let kind = match &error {
+			MsrError::PermissionDenied => io::ErrorKind::PermissionDenied,
+			MsrError::NotFound => io::ErrorKind::Unsupported,
+			MsrError::ModuleNotLoaded => io::ErrorKind::NotFound,
+			MsrError::IoError(e) => e.kind(),
+		};
+		Self::new(kind, error)
+	}
+}
+
+/// Open `/dev/cpu/N/msr` device nodes by CPU, so polling every core does not open and close a
+/// file for each read. One file serves every MSR of its CPU, read at the MSR address as offset.
+#[derive(Debug, Default)]
+pub struct MsrCache {
+	files: HashMap<usize, Arc<File>>,
+}
+
+impl MsrCache {
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn file(&mut self, cpu_id: usize) -> io::Result<Arc<File>> {
+		if let Some(file) = self.files.get(&cpu_id) {
+			return Ok(Arc::clone(file));
+		}
+		let file = Arc::new(File::open(format!("/dev/cpu/{cpu_id}/msr")).map_err(MsrError::from)?);
+		self.files.insert(cpu_id, Arc::clone(&file));
+		Ok(file)
+	}
+
+	/// Closes the file of a CPU, e.g. after it went offline, so the next read reopens it.
+	fn evict(&mut self, cpu_id: usize) {
+		self.files.remove(&cpu_id);
+	}
+
+	pub fn read_cached(&mut self, address: u32, cpu_id: usize) -> io::Result<u64> {
+		let file = self.file(cpu_id)?;
+		let result = read_msr_file(&file, address);
+		if result.is_err() {
+			self.evict(cpu_id);
+		}
+		result
+	}
+}
+
+fn read_msr_file(file: &File, address: u32) -> io::Result<u64> {
+	let mut value = [0u8; 8];
+	file.read_exact_at(&mut value, u64::from(address))
+		.map_err(MsrError::from)?;
+	Ok(u64::from_le_bytes(value))
+}
+
+/// Process-wide cache behind `read_msr`. The lock is only held to look up the file, so reads of
+/// different cores still run concurrently.
+fn msr_cache() -> &'static Mutex<MsrCache> {
+	static MSR_CACHE: OnceLock<Mutex<MsrCache>> = OnceLock::new();
+	MSR_CACHE.get_or_init(Mutex::default)
+}
+
+pub fn read_msr(msr_address: u32, core_id: usize) -> io::Result<u64> {
+	let file = msr_cache()
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.file(core_id)?;
+	let result = read_msr_file(&file, msr_address);
+	if result.is_err() {
+		msr_cache()
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.evict(core_id);
+	}
+	result
+}
+
+/// Writes an MSR from `WRITABLE_MSRS` and reads it back to confirm the CPU accepted the value.
+/// Other addresses fail with `PermissionDenied`.
+pub fn write_msr(msr_address: u32, core_id: usize, value: u64) -> io::Result<()> {
+	let Some(&(_, name)) = WRITABLE_MSRS.iter().find(|(address, _)| *address == msr_address) else {
+		return Err(io::Error::new(
+			io::ErrorKind::PermissionDenied,
+			format!("MSR {msr_address:#x} is not in the list of writable MSRs"),
+		));
+	};
+
+	let old_value = read_msr(msr_address, core_id)?;
+	fs::OpenOptions::new()
+		.write(true)
+		.open(format!("/dev/cpu/{core_id}/msr"))?
+		.write_all_at(&value.to_le_bytes(), u64::from(msr_address))?;
+
+	let new_value = read_msr(msr_address, core_id)?;
+	eprintln!("{name} on CPU {core_id}: {old_value:#x} -> {new_value:#x}");
+	if new_value != value {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("{name} on CPU {core_id} reads back {new_value:#x} after writing {value:#x}"),
+		));
+	}
+	Ok(())
+}
+
+/// The energy unit is a CPU-wide constant, so the MSR is only read on the first call.
+pub fn get_energy_unit(cpu_type: &CpuType) -> io::Result<u64> {
+	static ENERGY_UNIT: OnceLock<u64> = OnceLock::new();
+	if let Some(&energy_unit) = ENERGY_UNIT.get() {
+		return Ok(energy_unit);
+	}
+
+	let (unit_msr, energy_unit) = match cpu_type {
+		CpuType::Intel => {
+			let unit_msr = read_msr(INTEL_POWER_UNIT_MSR, 0)?;
+			(
+				unit_msr,
+				(unit_msr >> ENERGY_STATUS_UNIT_SHIFT) & ENERGY_STATUS_UNIT_MASK,
+			)
+		},
+		CpuType::Amd => {
+			let unit_msr = read_msr(AMD_ENERGY_UNIT_MSR, 0)?;
+			(unit_msr, validate_amd_energy_unit(unit_msr)?)
+		},
+		CpuType::Unsupported => return Err(io::Error::new(io::ErrorKind::Unsupported, "Unsupported CPU type")),
+	};
+	if let Some((_, min, max)) = EXPECTED_ENERGY_UNITS.iter().find(|(expected, ..)| expected == cpu_type) {
+		if !(min..=max).contains(&&energy_unit) {
+			eprintln!(
+				"Warning: energy unit {energy_unit} (unit MSR {unit_msr:#x}) is outside the expected range \
+				 {min}-{max} for {cpu_type:?} CPUs; power readings may be scaled wrong."
+			);
+		}
+	}
+	Ok(*ENERGY_UNIT.get_or_init(|| energy_unit))
+}
+
+/// Decodes the AMD energy unit and checks it against the wall clock. The PPR puts the energy unit
+/// in bits 12:8 of the unit MSR as on Intel, but bits 4:0 have been read as the energy unit on Zen
+/// 3 and later, so the package counter is measured over `ENERGY_UNIT_CHECK_INTERVAL` and scaled by
+/// both. Bits 12:8 are used unless only bits 4:0 give a plausible package power, with a warning
+/// whenever the two disagree on plausibility.
+pub fn validate_amd_energy_unit(unit_msr: u64) -> io::Result<u64> {
+	let documented = (unit_msr >> ENERGY_STATUS_UNIT_SHIFT) & ENERGY_STATUS_UNIT_MASK;
+	let alternative = unit_msr & AMD_ALT_ENERGY_UNIT_MASK;
+	if documented == alternative {
+		return Ok(documented);
+	}
+
+	let start = read_msr(AMD_ENERGY_PKG_MSR, 0)?;
+	let started_at = Instant::now();
+	thread::sleep(ENERGY_UNIT_CHECK_INTERVAL);
+	let end = read_msr(AMD_ENERGY_PKG_MSR, 0)?;
+	let elapsed_s = started_at.elapsed().as_secs_f64();
+	let steps = end.wrapping_sub(start) & get_energy_counter_mask(&CpuType::Amd);
+	// An idle package may not move the counter at all, which says nothing about the unit
+	if steps == 0 {
+		return Ok(documented);
+	}
+	let watts = |unit: u64| steps as f64 / (1u64 << unit) as f64 / elapsed_s;
+	let plausible = |unit: u64| watts(unit) <= MAX_PLAUSIBLE_PACKAGE_WATTS;
+
+	match (plausible(documented), plausible(alternative)) {
+		(false, true) => {
+			eprintln!(
+				"Warning: energy unit {documented} from bits 12:8 of unit MSR {unit_msr:#x} gives {:.0} W, using \
+				 {alternative} from bits 4:0 ({:.1} W) instead.",
+				watts(documented),
+				watts(alternative)
+			);
+			Ok(alternative)
+		},
+		(true, false) => Ok(documented),
+		(documented_plausible, _) => {
+			let verdict = if documented_plausible { "both" } else { "neither" };
+			eprintln!(
+				"Warning: {verdict} energy unit {documented} (bits 12:8) and {alternative} (bits 4:0) of unit MSR \
+				 {unit_msr:#x} give a plausible package power ({:.1} W vs {:.1} W); using {documented}.",
+				watts(documented),
+				watts(alternative)
+			);
+			Ok(documented)
+		},
+	}
+}
+
+/// Width of an energy status counter, which wraps back to 0 after its highest value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterWidth {
+	Bits32,
+	Bits64,
+}
+
+impl CounterWidth {
+	#[must_use]
+	pub const fn bits(self) -> u32 {
+		match self {
+			Self::Bits32 => 32,
+			Self::Bits64 => 64,
+		}
+	}
+
+	/// Mask covering the valid bits of the counter.
+	#[must_use]
+	pub const fn mask(self) -> u64 {
+		u64::MAX >> (64 - self.bits())
+	}
+}
+
+/// Width of the package energy counter. Both the Intel SDM and the AMD PPR document 32 bits with
+/// the upper half reserved, so the counter is only treated as wider when its upper half is in use
+/// in either of two reads 1 ms apart.
+#[must_use]
+pub fn detect_energy_counter_width(cpu_type: &CpuType) -> CounterWidth {
+	let msr_address = match cpu_type {
+		CpuType::Intel => INTEL_PKG_ENERGY_MSR,
+		CpuType::Amd => AMD_ENERGY_PKG_MSR,
+		CpuType::Unsupported => return CounterWidth::Bits32,
+	};
+	let Ok(first) = read_msr(msr_address, 0) else {
+		return CounterWidth::Bits32;
+	};
+	thread::sleep(Duration::from_millis(1));
+	let Ok(second) = read_msr(msr_address, 0) else {
+		return CounterWidth::Bits32;
+	};
+	if first >> 32 != 0 || second >> 32 != 0 {
+		CounterWidth::Bits64
+	} else {
+		CounterWidth::Bits32
+	}
+}
+
+/// Width of the energy counters, detected once on the first call.
+#[must_use]
+pub fn get_energy_counter_width(cpu_type: &CpuType) -> CounterWidth {
+	static COUNTER_WIDTH: OnceLock<CounterWidth> = OnceLock::new();
+	*COUNTER_WIDTH.get_or_init(|| detect_energy_counter_width(cpu_type))
+}
+
+/// Mask covering the valid bits of the energy counters, detected once on the first call.
+#[must_use]
+pub fn get_energy_counter_mask(cpu_type: &CpuType) -> u64 {
+	get_energy_counter_width(cpu_type).mask()
+}
+
+/// Reads the hardware-reported performance levels. Fails on CPUs without HWP support.
+pub fn read_hwp_capabilities(cpu_type: &CpuType) -> io::Result<HwpCapabilities> {
+	match cpu_type {
+		CpuType::Intel => Ok(HwpCapabilities::from_msr(read_msr(INTEL_HWP_CAPABILITIES_MSR, 0)?)),
+		_ => Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"HWP is only available on Intel CPUs",
+		)),
+	}
+}
+
+pub fn measure_msr_latency(cpu_type: &CpuType) -> io::Result<Duration> {
+	let msr_address = match cpu_type {
+		CpuType::Intel => INTEL_PKG_ENERGY_MSR,
+		CpuType::Amd => AMD_ENERGY_PKG_MSR,
+		CpuType::Unsupported => return Err(io::Error::new(io::ErrorKind::Unsupported, "Unsupported CPU type")),
+	};
+
+	let start = Instant::now();
+	for _ in 0..MSR_LATENCY_SAMPLES {
+		read_msr(msr_address, 0)?;
+	}
+	Ok(start.elapsed() / MSR_LATENCY_SAMPLES)
+}
+
+/// Warns when reading the energy MSRs of every core takes more than half of the sampling
+/// `interval`, since the reads themselves then skew the measured interval.
+pub fn check_msr_latency(cpu_type: &CpuType, physical_cores: usize, interval: Duration) -> io::Result<()> {
+	let avg_msr_latency_us = measure_msr_latency(cpu_type)?.as_secs_f64() * 1_000_000.0;
+	let snapshot_latency_us = avg_msr_latency_us * physical_cores as f64;
+
+	if snapshot_latency_us > interval.as_secs_f64() * 1_000_000.0 * 0.5 {
+		let suggested_interval_ms = (snapshot_latency_us * 2.0 / 1000.0).ceil();
+		eprintln!(
+			"Warning: MSR reads take {avg_msr_latency_us:.0} µs each, which is more than half of the {} ms \
+			 sampling interval across {physical_cores} cores. Consider a sampling interval of at least \
+			 {suggested_interval_ms} ms.",
+			interval.as_millis()
+		);
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cpu_type_parses_every_spelling() {
+		for (name, cpu_type) in [
+			("Intel", CpuType::Intel),
+			("intel", CpuType::Intel),
+			("AMD", CpuType::Amd),
+			("amd", CpuType::Amd),
+			("Unsupported", CpuType::Unsupported),
+			("unknown", CpuType::Unsupported),
+		] {
+			assert_eq!(name.parse(), Ok(cpu_type));
+		}
+	}
+
+	#[test]
+	fn cpu_type_round_trips_through_as_str() {
+		for cpu_type in [CpuType::Intel, CpuType::Amd, CpuType::Unsupported] {
+			assert_eq!(cpu_type.as_str().parse(), Ok(cpu_type));
+		}
+	}
+
+	#[test]
+	fn cpu_type_rejects_other_names() {
+		for name in ["", "INTEL", "Amd", "arm", " intel"] {
+			assert_eq!(name.parse::<CpuType>(), Err(CpuTypeParseError(name.to_string())));
+		}
+	}
+
+	#[test]
+	fn core_type_round_trips_through_as_str() {
+		for core_type in [
+			CoreType::Performance,
+			CoreType::Efficiency,
+			CoreType::LowPowerEfficiency,
+			CoreType::Unknown,
+		] {
+			assert_eq!(core_type.as_str().parse(), Ok(core_type));
+		}
+	}
+
+	#[test]
+	fn core_type_parses_short_names() {
+		for (name, core_type) in [
+			("P-core", CoreType::Performance),
+			("pcore", CoreType::Performance),
+			("E-core", CoreType::Efficiency),
+			("ecore", CoreType::Efficiency),
+			("LP-E", CoreType::LowPowerEfficiency),
+			("lpe", CoreType::LowPowerEfficiency),
+			("Unknown", CoreType::Unknown),
+			("unknown", CoreType::Unknown),
+		] {
+			assert_eq!(name.parse(), Ok(core_type), "{name}");
+		}
+	}
+
+	#[test]
+	fn core_type_rejects_other_names() {
+		for name in ["", "P", "e", "p-core", "Atom", "LPE"] {
+			assert_eq!(name.parse::<CoreType>(), Err(CoreTypeParseError(name.to_string())));
+		}
+	}
+}
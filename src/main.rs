@@ -1,236 +1,835 @@
-use msru::{Accessor, Msr};
-use std::collections::VecDeque;
-use std::io::{self, Write};
-use std::time::{Duration, Instant};
-use std::{fs, thread};
-
-// AMD RAPL MSR addresses
-const AMD_ENERGY_UNIT_MSR: u32 = 0xC001_0299;
-const AMD_ENERGY_CORE_MSR: u32 = 0xC001_029A;
-const AMD_ENERGY_PKG_MSR: u32 = 0xC001_029B;
-
-// Intel RAPL MSR addresses
-const INTEL_POWER_UNIT_MSR: u32 = 0x606;
-const INTEL_PKG_ENERGY_MSR: u32 = 0x611;
-const INTEL_CORE_ENERGY_MSR: u32 = 0x639;
-
-const DATA_COLLECTION_INTERVAL_MS: u64 = 100;
-const DISPLAY_UPDATE_INTERVAL_MS: u64 = 200;
-const AVERAGING_ITERATIONS: usize = 10;
-const POWER_SCALE: u64 = 1_000_000;
-
-#[derive(Debug)]
-enum CpuType {
-	Intel,
-	Amd,
-	Unsupported,
+use cpu_power::bench::bench_command;
+use cpu_power::cgroup::{CgroupStats, estimate_cgroup_power};
+use cpu_power::daemon::{DEFAULT_SOCKET_PATH, DaemonServer, query_reading};
+use cpu_power::display::{AdaptiveRefreshRate, DisplayLayout, OutputMode};
+use cpu_power::energy::{EnergySource, measure_power_sample};
+use cpu_power::fabric::{FclkTracker, format_fabric_clocks, read_fabric_clocks};
+use cpu_power::info::{HardwareInfo, detect_tdp, read_rapl_limits};
+use cpu_power::keyboard::{RawTerminal, spawn_key_reader};
+use cpu_power::monitor::{AveragingMode, MonitorState, PowerMonitor};
+use cpu_power::output::csv::CsvLogger;
+use cpu_power::profile::CallStackProfiler;
+#[cfg(feature = "serde")]
+use cpu_power::replay;
+use cpu_power::signal::{install_resize_handler, install_shutdown_handler};
+use cpu_power::stats::{HourlyBuckets, format_rfc3339};
+use cpu_power::topology::CpuTopology;
+use cpu_power::{
+	AVERAGING_ITERATIONS, CpuType, DATA_COLLECTION_INTERVAL_MS, DISPLAY_UPDATE_INTERVAL_MS, MIN_COLLECTION_INTERVAL_MS,
+	MonitorConfig, VM_RAPL_CONFIDENCE, detect_cpu_type, display, get_energy_counter_width, read_hwp_capabilities,
+	running_in_vm,
+};
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::time::{Instant, SystemTime};
+
+/// Seconds of samples kept for the `--stats` percentiles unless `--stats-window` is given
+const DEFAULT_STATS_WINDOW_SECS: u64 = 300;
+
+const JOULES_PER_KWH: f64 = 3.6e6;
+/// Where `--profile-pid` writes its flame graph
+const FLAME_GRAPH_PATH: &str = "flame.json";
+/// Topic prefix of MQTT messages unless `--mqtt-topic` is given
+#[cfg(feature = "mqtt")]
+const DEFAULT_MQTT_TOPIC: &str = "cpu-power";
+
+const USAGE: &str = "\
+Usage: cpu-power [info] [options]
+       cpu-power bench [options] -- <command> [args...]
+       cpu-power daemon|query [--socket <path>]
+
+Commands:
+  info                  Print the detected hardware and readable RAPL domains
+  bench                 Run a command and report the package energy it used
+  daemon                Stream JSON readings to clients of a Unix socket
+  query                 Print one JSON reading from a running daemon
+
+Options:
+  --json                Print `info` and `bench` results as JSON, or one JSON line per reading
+  --compact             Print one short line per display interval, for status bars
+  --hourly-report       Log the average power and total energy of each UTC hour
+  --adaptive-refresh    Redraw faster while package power changes
+  --trust-vm-rapl       Do not warn about RAPL readings inside a virtual machine, nor lower
+                        their confidence
+  --debug               Show a histogram of the actual sample intervals
+  --once                Print a single measurement over one sampling interval and exit
+  --interval <ms>       Length of each sample in milliseconds (default 100, at least 10)
+  --window <N>          Average the last N samples (default 10)
+  --stats               Print P50/P95/P99/max package and core power on exit
+  --stats-window <S>    Seconds of samples the --stats percentiles cover (default 300)
+  --ema-alpha <A>       Smooth with an exponential moving average of weight A (0-1)
+  --cgroup <path>       Show the power share of a cgroup v2 by its CPU time
+  --csv-out <path>      Append every reading to a CSV file
+  --tdp-watts <W>       TDP to use when it cannot be detected
+  --energy-budget-kwh <N>
+                        Alert once the session uses N kWh of package energy
+  --record <path>       Save every reading to an NDJSON file (serde feature)
+  --replay <path>       Play back a --record file instead of measuring (serde feature)
+  --socket <path>       Socket of daemon and query (default /run/cpu-power/monitor.sock)
+  --alert <W>           Warn on stderr on every reading with package power above W watts
+  --alert-once          Warn only when power rises above the --alert threshold, not again
+                        until it dropped back below it
+  --profile-pid <PID>   Attribute core power to the call stacks of a process and write
+                        flame.json when it exits or on Ctrl+C
+  --mqtt-broker <host:port>
+                        Publish every reading to an MQTT broker (mqtt feature)
+  --mqtt-topic <topic>  Topic prefix of the MQTT messages (default cpu-power)
+  --mqtt-qos <0|1|2>    QoS of the MQTT messages (default 0)
+  --mqtt-retain         Have the broker retain the latest MQTT messages
+  --tui                 Show a full-screen dashboard instead (tui feature)
+  -h, --help            Print this help
+
+Keys, in the terminal display and the dashboard:
+  p                     Pause and resume sampling
+  c                     Clear the averages, history and session totals
+  q, Ctrl+C             Quit, or Esc in the dashboard
+
+Keys while monitoring:
+  p                     Pause or resume sampling
+  q, Ctrl+C             Quit";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+enum Command {
+	#[default]
+	Monitor,
+	Info,
+	/// Command line of the program to benchmark
+	Bench(Vec<String>),
+	Daemon,
+	Query,
+	Help,
 }
 
-struct PowerReading {
-	package: f64,
-	cores: Vec<f64>,
+#[derive(Debug, Default)]
+struct Options {
+	command: Command,
+	json: bool,
+	compact: bool,
+	hourly_report: bool,
+	adaptive_refresh: bool,
+	trust_vm_rapl: bool,
+	debug: bool,
+	tdp_watts: Option<f64>,
+	csv_out: Option<PathBuf>,
+	cgroup: Option<PathBuf>,
+	window: Option<usize>,
+	interval_ms: Option<u64>,
+	ema_alpha: Option<f64>,
+	stats: bool,
+	stats_window_secs: Option<u64>,
+	alert_watts: Option<f64>,
+	alert_once: bool,
+	energy_budget_kwh: Option<f64>,
+	socket: Option<PathBuf>,
+	profile_pid: Option<libc::pid_t>,
+	once: bool,
+	#[cfg(feature = "tui")]
+	tui: bool,
+	#[cfg(feature = "serde")]
+	record: Option<PathBuf>,
+	#[cfg(feature = "serde")]
+	replay: Option<PathBuf>,
+	#[cfg(feature = "mqtt")]
+	mqtt_broker: Option<String>,
+	#[cfg(feature = "mqtt")]
+	mqtt_topic: Option<String>,
+	#[cfg(feature = "mqtt")]
+	mqtt_qos: Option<u8>,
+	#[cfg(feature = "mqtt")]
+	mqtt_retain: bool,
 }
 
-struct EnergySnapshot {
-	package: u64,
-	cores: Vec<u64>,
-}
-
-struct PowerMonitor {
-	power_readings: VecDeque<u64>,
-	core_power_readings: Vec<VecDeque<u64>>,
-	last_display_time: Instant,
-}
-
-impl PowerMonitor {
-	fn new(physical_cores: usize) -> Self {
-		Self {
-			power_readings: VecDeque::with_capacity(AVERAGING_ITERATIONS),
-			core_power_readings: vec![VecDeque::with_capacity(AVERAGING_ITERATIONS); physical_cores],
-			last_display_time: Instant::now(),
+impl Options {
+	/// Sampling timing from `--interval` and `--window`. The display never redraws faster than
+	/// new samples arrive.
+	fn monitor_config(&self) -> MonitorConfig {
+		let collection_interval_ms = self.interval_ms.unwrap_or(DATA_COLLECTION_INTERVAL_MS);
+		MonitorConfig {
+			collection_interval_ms,
+			display_interval_ms: DISPLAY_UPDATE_INTERVAL_MS.max(collection_interval_ms),
+			averaging_window: self.window.unwrap_or(AVERAGING_ITERATIONS),
+			confidence: if !self.trust_vm_rapl && running_in_vm() {
+				VM_RAPL_CONFIDENCE
+			} else {
+				1.0
+			},
+			..MonitorConfig::default()
 		}
 	}
 
-	fn update_readings(&mut self, package_power: u64, core_powers: &[u64]) {
-		self.power_readings.push_back(package_power);
-		if self.power_readings.len() > AVERAGING_ITERATIONS {
-			self.power_readings.pop_front();
-		}
-
-		for (core_id, &power) in core_powers.iter().enumerate() {
-			self.core_power_readings[core_id].push_back(power);
-			if self.core_power_readings[core_id].len() > AVERAGING_ITERATIONS {
-				self.core_power_readings[core_id].pop_front();
-			}
+	/// `--compact` lines, as JSON with `--json`, or else the mode that suits stdout.
+	fn output_mode(&self) -> OutputMode {
+		if self.compact {
+			OutputMode::Compact { json: self.json }
+		} else {
+			OutputMode::detect(self.json)
 		}
 	}
+}
 
-	fn calculate_averages(&self) -> PowerReading {
-		let package_avg = self.calculate_average_power(&self.power_readings);
-		let cores: Vec<f64> = self
-			.core_power_readings
-			.iter()
-			.map(|readings| self.calculate_average_power(readings))
-			.collect();
+fn parse_value<T: FromStr>(name: &str, value: Option<String>) -> io::Result<T> {
+	let value = value.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{name} needs a value")))?;
+	value.parse().map_err(|_| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("Invalid value for {name}: {value}"),
+		)
+	})
+}
 
-		PowerReading {
-			package: package_avg,
-			cores,
+fn parse_args() -> io::Result<Options> {
+	let mut options = Options::default();
+	let mut args = env::args().skip(1).enumerate();
+	while let Some((index, arg)) = args.next() {
+		match arg.as_str() {
+			"info" if index == 0 => options.command = Command::Info,
+			"bench" if index == 0 => options.command = Command::Bench(Vec::new()),
+			"daemon" if index == 0 => options.command = Command::Daemon,
+			"query" if index == 0 => options.command = Command::Query,
+			"--" if matches!(options.command, Command::Bench(_)) => {
+				options.command = Command::Bench(args.by_ref().map(|(_, arg)| arg).collect());
+			},
+			"-h" | "--help" => options.command = Command::Help,
+			"--json" => options.json = true,
+			"--compact" => options.compact = true,
+			"--hourly-report" => options.hourly_report = true,
+			"--adaptive-refresh" => options.adaptive_refresh = true,
+			"--trust-vm-rapl" => options.trust_vm_rapl = true,
+			"--debug" => options.debug = true,
+			"--once" => options.once = true,
+			"--stats" => options.stats = true,
+			"--stats-window" => {
+				let secs: u64 = parse_value(&arg, args.next().map(|(_, value)| value))?;
+				if secs == 0 {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidInput,
+						"--stats-window must be at least 1",
+					));
+				}
+				options.stats_window_secs = Some(secs);
+			},
+			#[cfg(feature = "tui")]
+			"--tui" => options.tui = true,
+			"--window" => {
+				let window: usize = parse_value(&arg, args.next().map(|(_, value)| value))?;
+				if window == 0 {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidInput,
+						"--window must be at least 1",
+					));
+				}
+				options.window = Some(window);
+			},
+			"--interval" => {
+				let interval_ms: u64 = parse_value(&arg, args.next().map(|(_, value)| value))?;
+				if interval_ms < MIN_COLLECTION_INTERVAL_MS {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidInput,
+						format!("--interval must be at least {MIN_COLLECTION_INTERVAL_MS} ms"),
+					));
+				}
+				options.interval_ms = Some(interval_ms);
+			},
+			"--ema-alpha" => {
+				let alpha: f64 = parse_value(&arg, args.next().map(|(_, value)| value))?;
+				if !(alpha > 0.0 && alpha <= 1.0) {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidInput,
+						"--ema-alpha must be greater than 0 and at most 1",
+					));
+				}
+				options.ema_alpha = Some(alpha);
+			},
+			#[cfg(feature = "serde")]
+			"--record" => options.record = Some(parse_value(&arg, args.next().map(|(_, value)| value))?),
+			#[cfg(feature = "serde")]
+			"--replay" => options.replay = Some(parse_value(&arg, args.next().map(|(_, value)| value))?),
+			#[cfg(feature = "mqtt")]
+			"--mqtt-broker" => options.mqtt_broker = Some(parse_value(&arg, args.next().map(|(_, value)| value))?),
+			#[cfg(feature = "mqtt")]
+			"--mqtt-topic" => options.mqtt_topic = Some(parse_value(&arg, args.next().map(|(_, value)| value))?),
+			#[cfg(feature = "mqtt")]
+			"--mqtt-qos" => {
+				let qos: u8 = parse_value(&arg, args.next().map(|(_, value)| value))?;
+				cpu_power::output::mqtt::parse_qos(qos)?;
+				options.mqtt_qos = Some(qos);
+			},
+			#[cfg(feature = "mqtt")]
+			"--mqtt-retain" => options.mqtt_retain = true,
+			"--profile-pid" => options.profile_pid = Some(parse_value(&arg, args.next().map(|(_, value)| value))?),
+			"--socket" => options.socket = Some(parse_value(&arg, args.next().map(|(_, value)| value))?),
+			"--cgroup" => options.cgroup = Some(parse_value(&arg, args.next().map(|(_, value)| value))?),
+			"--csv-out" => options.csv_out = Some(parse_value(&arg, args.next().map(|(_, value)| value))?),
+			"--tdp-watts" => options.tdp_watts = Some(parse_value(&arg, args.next().map(|(_, value)| value))?),
+			"--alert" => options.alert_watts = Some(parse_value(&arg, args.next().map(|(_, value)| value))?),
+			"--alert-once" => options.alert_once = true,
+			"--energy-budget-kwh" => {
+				let kwh: f64 = parse_value(&arg, args.next().map(|(_, value)| value))?;
+				if !kwh.is_finite() || kwh <= 0.0 {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidInput,
+						"--energy-budget-kwh must be a number greater than 0",
+					));
+				}
+				options.energy_budget_kwh = Some(kwh);
+			},
+			_ => {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidInput,
+					format!("Unknown argument: {arg}"),
+				));
+			},
 		}
 	}
-
-	fn calculate_average_power(&self, readings: &VecDeque<u64>) -> f64 {
-		let total: u64 = readings.iter().sum();
-		total as f64 / readings.len() as f64 / POWER_SCALE as f64
-	}
-
-	fn should_update_display(&self) -> bool {
-		self.last_display_time.elapsed().as_millis() >= u128::from(DISPLAY_UPDATE_INTERVAL_MS)
+	if options.command == Command::Bench(Vec::new()) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"bench needs a command after --",
+		));
 	}
+	Ok(options)
 }
 
-fn detect_cpu_type() -> CpuType {
-	let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
-	if cpuinfo.contains("GenuineIntel") {
-		CpuType::Intel
-	} else if cpuinfo.contains("AuthenticAMD") {
-		CpuType::Amd
+fn monitor_cpu_power(cpu_type: &CpuType, options: &Options, config: &MonitorConfig) -> io::Result<()> {
+	// Without a terminal to read keys from, Ctrl+C stays a signal
+	let output = options.output_mode();
+	let raw_terminal = io::stdin().is_terminal().then(|| RawTerminal::enable().ok()).flatten();
+	let keys = raw_terminal.as_ref().and_then(|_| spawn_key_reader().ok());
+
+	// Keep stdout clean for JSON, plain and compact lines
+	let mut banner: Box<dyn Write> = match output {
+		OutputMode::Terminal => Box::new(io::stdout()),
+		OutputMode::Json | OutputMode::Plain | OutputMode::Compact { .. } => Box::new(io::stderr()),
+	};
+	writeln!(
+		banner,
+		"Monitoring CPU Power Usage (Watts) every {} ms...",
+		config.collection_interval_ms
+	)?;
+	if keys.is_some() {
+		writeln!(banner, "Press p to pause, q or Ctrl+C to stop.")?;
 	} else {
-		CpuType::Unsupported
+		writeln!(banner, "Press Ctrl+C to stop.")?;
 	}
-}
+	writeln!(banner)?;
 
-fn read_msr(msr_address: u32, core_id: usize) -> io::Result<u64> {
-	Msr::new(msr_address, core_id as u16)
-		.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
-		.read()
-		.map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-}
+	let source = EnergySource::detect(cpu_type)?;
+	if let Some(note) = source.note() {
+		writeln!(banner, "{note}")?;
+	}
+	let mut topology = CpuTopology::detect();
+	topology.detect_preferred_cores(cpu_type);
+	topology.detect_ccx(cpu_type);
+	topology.detect_core_types(cpu_type);
+	let physical_cores = topology.physical_cores();
+
+	if options.debug && source.reads_msrs() {
+		let counter_bits = get_energy_counter_width(cpu_type).bits();
+		writeln!(banner, "Energy counter width: {counter_bits} bits")?;
+	}
 
-const fn calculate_power_uw(energy_start: u64, energy_end: u64, time_interval_ms: u64, energy_unit: u64) -> u64 {
-	let energy_difference = if energy_end < energy_start {
-		energy_end + 0xFFFF_FFFF - energy_start
-	} else {
-		energy_end - energy_start
+	let mut hwp_supported = read_hwp_capabilities(cpu_type).is_ok();
+	let rapl_limits = read_rapl_limits(cpu_type).filter(|limits| limits.pl1_watts > 0.0);
+
+	let mut monitor = PowerMonitor::with_config(physical_cores, config);
+	if let Some(alpha) = options.ema_alpha {
+		monitor.set_averaging_mode(AveragingMode::Exponential(alpha));
+	}
+	if options.stats {
+		let secs = options.stats_window_secs.unwrap_or(DEFAULT_STATS_WINDOW_SECS);
+		monitor.set_stats_history((secs * 1000 / config.collection_interval_ms) as usize);
+	}
+	monitor.calibrate(cpu_type, &source)?;
+	monitor.tdp = detect_tdp(cpu_type, options.tdp_watts);
+	if let Some(alert_watts) = options.alert_watts {
+		monitor.set_threshold_alert(alert_watts, move |reading| {
+			eprintln!("Alert: package power {:.2} W exceeds {alert_watts} W", reading.package);
+		});
+		monitor.set_threshold_alert_repeat(!options.alert_once);
+	}
+	if let Some(kwh) = options.energy_budget_kwh {
+		monitor.set_energy_budget_joules(kwh * JOULES_PER_KWH);
+		monitor.set_budget_alert(|reading| {
+			if let Some(budget) = reading.energy_budget {
+				eprintln!(
+					"time={} event=energy_budget_exhausted used_kj={:.2} budget_kj={:.2} package_w={:.2}",
+					format_rfc3339(SystemTime::now()),
+					budget.used_joules / 1000.0,
+					budget.budget_joules / 1000.0,
+					reading.package
+				);
+			}
+		});
+	}
+	let mut csv_logger = options
+		.csv_out
+		.as_ref()
+		.map(|path| CsvLogger::open(path, physical_cores))
+		.transpose()?;
+	#[cfg(feature = "mqtt")]
+	let mut mqtt = options
+		.mqtt_broker
+		.as_deref()
+		.map(|broker| {
+			cpu_power::output::mqtt::MqttPublisher::connect(
+				broker,
+				options.mqtt_topic.as_deref().unwrap_or(DEFAULT_MQTT_TOPIC),
+				cpu_power::output::mqtt::parse_qos(options.mqtt_qos.unwrap_or(0))?,
+				options.mqtt_retain,
+			)
+		})
+		.transpose()?;
+	let mut hourly = options.hourly_report.then(HourlyBuckets::default);
+	let mut refresh = options.adaptive_refresh.then(|| AdaptiveRefreshRate::new(config));
+	// CPU time of the cgroup at the previous redraw
+	let mut cgroup_stats = options.cgroup.as_deref().map(CgroupStats::read).transpose()?;
+
+	let mut status_line_count = usize::from(options.debug);
+	let mut stdout = io::stdout();
+	let mut layout = DisplayLayout::new(display::detect_terminal_width());
+	let mut total_lines = match output {
+		OutputMode::Terminal => {
+			display::prepare_display_area(&topology, layout.cores_per_row, status_line_count, &mut stdout)?
+		},
+		OutputMode::Json | OutputMode::Plain | OutputMode::Compact { .. } => 0,
 	};
+	let resized = install_resize_handler()?;
+
+	// Follows the topology the monitor samples after CPUs were hotplugged
+	let mut display_topology = topology.clone();
+	let mut fabric_clocks_supported = matches!(cpu_type, CpuType::Amd) && read_fabric_clocks().is_some();
+	let mut fclk_tracker = FclkTracker::default();
+	let mut last_display_time = Instant::now();
+	let shutdown = install_shutdown_handler()?;
+	#[cfg(feature = "serde")]
+	let recorder = options.record.clone().map(|path| {
+		let (sender, receiver) = std::sync::mpsc::channel();
+		let recorder = std::thread::spawn(move || replay::record_to_file(&path, receiver));
+		(sender, recorder)
+	});
+	let result = monitor.monitor_with_callback(cpu_type, &topology, &source, |monitor, readings| {
+		if shutdown.load(Ordering::SeqCst) {
+			monitor.stop();
+		}
+		let topology_update = monitor.take_topology_update();
+		let layout_changed = topology_update.is_some() || resized.swap(false, Ordering::SeqCst);
+		if let Some(topology) = topology_update {
+			display_topology = topology;
+		}
+		if layout_changed && output == OutputMode::Terminal {
+			layout.resize(display::detect_terminal_width());
+			total_lines = display::prepare_display_area_update(
+				&display_topology,
+				layout.cores_per_row,
+				status_line_count,
+				total_lines,
+				&mut stdout,
+			)?;
+		}
+		for command in keys.iter().flat_map(|keys| keys.try_iter()) {
+			command.apply(monitor)?;
+		}
+		// Paused callbacks repeat the last reading, which must not be logged again
+		let measuring = monitor.state() == MonitorState::Measuring;
+		if let Some(csv_logger) = csv_logger.as_mut().filter(|_| measuring) {
+			csv_logger.write_reading(readings)?;
+		}
+		#[cfg(feature = "mqtt")]
+		if let Some(mqtt) = mqtt.as_mut().filter(|_| measuring) {
+			mqtt.publish(readings)?;
+		}
+		// The recorder only hangs up after failing, which is reported once monitoring stops
+		#[cfg(feature = "serde")]
+		if let Some((sender, _)) = recorder.as_ref().filter(|_| measuring) {
+			if sender.send(readings.clone()).is_err() {
+				monitor.stop();
+			}
+		}
+		if let Some((hourly, (pkg_mw, interval))) = hourly.as_mut().zip(monitor.last_package_sample()) {
+			if let Some(line) = hourly.record(pkg_mw / 1000.0, interval, SystemTime::now()) {
+				match output {
+					OutputMode::Terminal => {
+						// Print the log line over the display area and reserve a fresh one below it
+						write!(stdout, "\x1B[{total_lines}A\x1B[2K")?;
+						writeln!(stdout, "{line}")?;
+						display::prepare_display_area(
+							&display_topology,
+							layout.cores_per_row,
+							status_line_count,
+							&mut stdout,
+						)?;
+					},
+					OutputMode::Json | OutputMode::Plain | OutputMode::Compact { .. } => eprintln!("{line}"),
+				}
+			}
+		}
 
-	let energy_uj = (energy_difference * POWER_SCALE) >> energy_unit;
-	energy_uj * 1000 / time_interval_ms
-}
+		match output {
+			OutputMode::Json if measuring => return display::write_json_reading(&mut stdout, readings),
+			OutputMode::Plain if measuring => return display::plain_print_reading(&mut stdout, readings),
+			OutputMode::Json | OutputMode::Plain => return Ok(()),
+			// No display area to prepare or cursor to move, just a line per display interval
+			OutputMode::Compact { json } => {
+				if last_display_time.elapsed() < config.display_interval() {
+					return Ok(());
+				}
+				last_display_time = Instant::now();
+				return display::write_compact_reading(&mut stdout, readings, json);
+			},
+			OutputMode::Terminal => {},
+		}
 
-fn display_power_readings(readings: &PowerReading, physical_cores: usize) -> io::Result<()> {
-	let total_lines = (physical_cores + 1) / 2 + 2;
-	print!("\x1B[{total_lines}A");
+		let display_interval = refresh
+			.as_ref()
+			.map_or(config.display_interval(), AdaptiveRefreshRate::interval);
+
+		if last_display_time.elapsed() >= display_interval {
+			let hwp = if hwp_supported {
+				read_hwp_capabilities(cpu_type).ok()
+			} else {
+				None
+			};
+			hwp_supported = hwp.is_some();
+			let mut status_lines = Vec::with_capacity(status_line_count);
+			if options.debug {
+				status_lines.push(display::format_sample_interval_histogram(
+					monitor.sample_intervals(),
+					config.collection_interval(),
+				));
+			}
+			if fabric_clocks_supported {
+				match read_fabric_clocks() {
+					Some(clocks) => {
+						fclk_tracker.record(clocks.fclk_mhz, readings.package);
+						status_lines.push(format_fabric_clocks(&clocks, &fclk_tracker));
+					},
+					None => fabric_clocks_supported = false,
+				}
+			}
+			let pl2_eta = rapl_limits.and_then(|limits| monitor.time_to_power_limit(limits.pl2_watts));
+			if let Some(eta) = pl2_eta {
+				status_lines.push(format!("ETA to PL2: ~{:.1} s", eta.as_secs_f64()));
+			}
+			if let Some((path, previous)) = options.cgroup.as_deref().zip(cgroup_stats.as_mut()) {
+				let current = CgroupStats::read(path)?;
+				let usage = current.since(previous);
+				status_lines.push(format!(
+					"Cgroup {}: ~{:.2} W ({:.1}% of busy CPU time)",
+					path.display(),
+					estimate_cgroup_power(&usage, readings),
+					usage.cpu_share() * 100.0
+				));
+				*previous = current;
+			}
+			status_lines.push(format!(
+				"Session Energy: {:.2} kJ",
+				monitor.session_energy_joules() / 1000.0
+			));
+			if let Some(budget) = &readings.energy_budget {
+				status_lines.push(display::format_energy_budget(budget));
+			}
+			if monitor.state() == MonitorState::Paused {
+				status_lines.push(String::from("[PAUSED] press p to resume"));
+			}
+			if monitor.msr_error_count() > 0 {
+				status_lines.push(format!("Errors: {}", monitor.msr_error_count()));
+			}
+			if status_lines.len() > status_line_count {
+				total_lines = display::prepare_display_area_update(
+					&display_topology,
+					layout.cores_per_row,
+					status_lines.len(),
+					total_lines,
+					&mut stdout,
+				)?;
+				status_line_count = status_lines.len();
+			}
+			// Status lines that come and go must still fill the area the cursor moves over
+			status_lines.resize(status_line_count, String::new());
+			layout.record_package(readings.package);
+			display::display_power_readings(
+				readings,
+				&display_topology,
+				&layout,
+				hwp.as_ref(),
+				rapl_limits.as_ref(),
+				&status_lines,
+				&mut stdout,
+			)?;
+			if let Some(refresh) = refresh.as_mut() {
+				refresh.record_displayed(readings.package);
+			}
+			last_display_time = Instant::now();
+		}
+		Ok(())
+	});
+
+	let summary = monitor.session_summary();
+	writeln!(banner, "{}", display::format_session_summary(&summary))?;
+	writeln!(
+		banner,
+		"{}",
+		display::format_session_energy(summary.total_energy_joules, summary.elapsed)
+	)?;
+	writeln!(
+		banner,
+		"Package {}",
+		display::format_extremes(&monitor.pkg_power_extremes, summary.started_at)
+	)?;
+	let mut core_extremes: Vec<_> = monitor.core_power_extremes.iter().collect();
+	core_extremes.sort_unstable_by_key(|&(&core_id, _)| core_id);
+	for (core_id, extremes) in core_extremes {
+		writeln!(
+			banner,
+			"Core {core_id} {}",
+			display::format_extremes(extremes, summary.started_at)
+		)?;
+	}
+	if options.stats {
+		let columns = [("P50", 50.0), ("P95", 95.0), ("P99", 99.0), ("Max", 100.0)]
+			.into_iter()
+			.map(|(heading, p)| Ok((heading, monitor.percentile_reading(p)?)))
+			.collect::<io::Result<Vec<_>>>()?;
+		writeln!(banner, "{}", display::format_percentile_table(&columns))?;
+	}
+	for (kind, count) in monitor.msr_error_report() {
+		eprintln!("MSR read errors ({kind}): {count}");
+	}
+	#[cfg(feature = "mqtt")]
+	if let Some(dropped) = mqtt.map(|mqtt| mqtt.dropped_readings()).filter(|&dropped| dropped > 0) {
+		eprintln!("MQTT readings dropped while the broker was unreachable: {dropped}");
+	}
+	#[cfg(feature = "serde")]
+	if let Some((sender, recorder)) = recorder {
+		drop(sender);
+		recorder
+			.join()
+			.map_err(|_| io::Error::other("Recording thread panicked"))??;
+	}
+	result
+}
 
-	print!("\x1B[2K");
+/// Samples on a background thread and draws the readings with the full-screen dashboard.
+#[cfg(feature = "tui")]
+fn monitor_cpu_power_tui(cpu_type: &CpuType, options: &Options, config: &MonitorConfig) -> io::Result<()> {
+	let tdp_watts = detect_tdp(cpu_type, options.tdp_watts).map(|tdp| tdp.watts());
+	let summary = cpu_power::tui::monitor_with_dashboard(cpu_type, config, tdp_watts, |monitor| {
+		if let Some(alpha) = options.ema_alpha {
+			monitor.set_averaging_mode(AveragingMode::Exponential(alpha));
+		}
+		if let Some(kwh) = options.energy_budget_kwh {
+			monitor.set_energy_budget_joules(kwh * JOULES_PER_KWH);
+		}
+	})?;
+	println!("{}", display::format_session_summary(&summary));
 	println!(
-		"Package: {:6.2} W | Cores Total: {:6.2} W",
-		readings.package,
-		readings.cores.iter().sum::<f64>()
+		"{}",
+		display::format_session_energy(summary.total_energy_joules, summary.elapsed)
 	);
+	Ok(())
+}
 
-	print!("\x1B[2K");
-	println!();
-
-	for pair in (0..physical_cores).step_by(2) {
-		let core2_str = if pair + 1 < physical_cores {
-			format!("| Core {}:  {:5.2} W", pair + 1, readings.cores[pair + 1])
-		} else {
-			String::new()
-		};
-
-		print!("\x1B[2K");
-		println!("Core {}:   {:5.2} W {}", pair, readings.cores[pair], core2_str);
+/// Plays back a recording made with `--record` at the sampling rate, without touching the
+/// hardware. The cores are laid out in a single group, as CCXs and preferred cores are not
+/// recorded.
+#[cfg(feature = "serde")]
+fn replay_recording(path: &Path, options: &Options, config: &MonitorConfig) -> io::Result<()> {
+	let mut readings = replay::replay_from_file(path)?.peekable();
+	let Some(first) = readings.peek() else {
+		return Ok(());
+	};
+	let topology = CpuTopology::from_core_count(first.cores.len(), first.cores.len());
+	let mut stdout = io::stdout();
+	let output = options.output_mode();
+	let mut layout = DisplayLayout::new(display::detect_terminal_width());
+	if output == OutputMode::Terminal {
+		display::prepare_display_area(&topology, layout.cores_per_row, 0, &mut stdout)?;
 	}
-
-	io::stdout().flush()
+	for reading in readings {
+		match output {
+			OutputMode::Terminal => {
+				layout.record_package(reading.package);
+				display::display_power_readings(&reading, &topology, &layout, None, None, &[], &mut stdout)?;
+			},
+			OutputMode::Json => display::write_json_reading(&mut stdout, &reading)?,
+			OutputMode::Plain => display::plain_print_reading(&mut stdout, &reading)?,
+			OutputMode::Compact { json } => display::write_compact_reading(&mut stdout, &reading, json)?,
+		}
+		std::thread::sleep(config.collection_interval());
+	}
+	Ok(())
 }
 
-fn read_energy_snapshot(cpu_type: &CpuType, physical_cores: usize) -> io::Result<EnergySnapshot> {
-	match cpu_type {
-		CpuType::Intel => {
-			let package = read_msr(INTEL_PKG_ENERGY_MSR, 0)?;
-			let cores = vec![read_msr(INTEL_CORE_ENERGY_MSR, 0)?];
-			Ok(EnergySnapshot { package, cores })
-		},
-		CpuType::Amd => {
-			let package = read_msr(AMD_ENERGY_PKG_MSR, 0)?;
-			let cores = (0..physical_cores)
-				.map(|core_id| read_msr(AMD_ENERGY_CORE_MSR, core_id))
-				.collect::<Result<Vec<_>, _>>()?;
-			Ok(EnergySnapshot { package, cores })
-		},
-		CpuType::Unsupported => Err(io::Error::new(io::ErrorKind::Unsupported, "Unsupported CPU type")),
+/// Samples like the terminal display but serves the readings on the daemon socket, until SIGINT
+/// or SIGTERM.
+fn run_daemon(cpu_type: &CpuType, options: &Options, config: &MonitorConfig) -> io::Result<()> {
+	let source = EnergySource::detect(cpu_type)?;
+	if let Some(note) = source.note() {
+		eprintln!("{note}");
+	}
+	let mut topology = CpuTopology::detect();
+	topology.detect_ccx(cpu_type);
+	topology.detect_core_types(cpu_type);
+	let mut monitor = PowerMonitor::with_config(topology.physical_cores(), config);
+	if let Some(alpha) = options.ema_alpha {
+		monitor.set_averaging_mode(AveragingMode::Exponential(alpha));
 	}
+	monitor.calibrate(cpu_type, &source)?;
+
+	let path = options
+		.socket
+		.clone()
+		.unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET_PATH));
+	let server = DaemonServer::bind(&path)?;
+	eprintln!("Serving readings on {}", path.display());
+	let shutdown = install_shutdown_handler()?;
+	monitor.monitor_with_callback(cpu_type, &topology, &source, |monitor, reading| {
+		if shutdown.load(Ordering::SeqCst) {
+			monitor.stop();
+		}
+		server.broadcast(reading)
+	})
 }
 
-fn get_energy_unit(cpu_type: &CpuType) -> io::Result<u64> {
-	let unit_msr = match cpu_type {
-		CpuType::Intel => read_msr(INTEL_POWER_UNIT_MSR, 0)?,
-		CpuType::Amd => read_msr(AMD_ENERGY_UNIT_MSR, 0)?,
-		CpuType::Unsupported => return Err(io::Error::new(io::ErrorKind::Unsupported, "Unsupported CPU type")),
-	};
-	Ok((unit_msr >> 8) & 0x1F)
+/// Samples the call stacks of `pid` alongside the core power until it exits or SIGINT or SIGTERM,
+/// then writes the energy flame graph to `flame.json`.
+fn profile_process(cpu_type: &CpuType, pid: libc::pid_t, config: &MonitorConfig) -> io::Result<()> {
+	let source = EnergySource::detect(cpu_type)?;
+	if let Some(note) = source.note() {
+		eprintln!("{note}");
+	}
+	let topology = CpuTopology::detect();
+	let mut monitor = PowerMonitor::with_config(topology.physical_cores(), config);
+	monitor.calibrate(cpu_type, &source)?;
+	let mut profiler = CallStackProfiler::attach(pid, &topology)?;
+
+	eprintln!("Profiling process {pid}, press Ctrl+C to stop.");
+	let shutdown = install_shutdown_handler()?;
+	monitor.monitor_with_callback(cpu_type, &topology, &source, |monitor, reading| {
+		profiler.record(reading);
+		if shutdown.load(Ordering::SeqCst) || !profiler.is_running() {
+			monitor.stop();
+		}
+		Ok(())
+	})?;
+
+	let path = Path::new(FLAME_GRAPH_PATH);
+	profiler.write_flame_json(path)?;
+	eprintln!(
+		"Wrote the flame graph of {} samples to {}",
+		profiler.samples(),
+		path.display()
+	);
+	Ok(())
 }
 
-fn monitor_cpu_power(cpu_type: &CpuType) -> io::Result<()> {
-	println!("Monitoring CPU Power Usage (Watts) every {DATA_COLLECTION_INTERVAL_MS} ms...");
-	println!("Press Ctrl+C to stop.");
-	println!();
-
-	let energy_unit = get_energy_unit(cpu_type)?;
-	let physical_cores = num_cpus::get_physical();
-
-	let mut monitor = PowerMonitor::new(physical_cores);
-
-	let total_lines = (physical_cores + 1) / 2 + 2;
-	for _ in 0..total_lines {
-		println!();
+/// Runs the benchmarked command and exits with its exit code if it failed. Like `time`, the
+/// report goes to stderr to stay apart from the command's output, unless JSON was asked for.
+fn run_bench(cpu_type: &CpuType, argv: &[String], options: &Options) -> io::Result<()> {
+	let result = bench_command(cpu_type, &CpuTopology::detect(), argv)?;
+	if options.json {
+		println!("{}", result.to_json());
+	} else {
+		eprintln!("{result}");
 	}
+	if !result.status.success() {
+		std::process::exit(result.status.code().unwrap_or(1));
+	}
+	Ok(())
+}
 
-	loop {
-		let initial_snapshot = read_energy_snapshot(cpu_type, physical_cores)?;
-		thread::sleep(Duration::from_millis(DATA_COLLECTION_INTERVAL_MS));
-		let final_snapshot = read_energy_snapshot(cpu_type, physical_cores)?;
-
-		let pkg_power = calculate_power_uw(
-			initial_snapshot.package,
-			final_snapshot.package,
-			DATA_COLLECTION_INTERVAL_MS,
-			energy_unit,
-		);
-
-		let core_powers: Vec<u64> = initial_snapshot
-			.cores
-			.iter()
-			.zip(final_snapshot.cores.iter())
-			.map(|(&start, &end)| calculate_power_uw(start, end, DATA_COLLECTION_INTERVAL_MS, energy_unit))
-			.collect();
-
-		monitor.update_readings(pkg_power, &core_powers);
-
-		if monitor.should_update_display() {
-			let readings = monitor.calculate_averages();
-			display_power_readings(&readings, physical_cores)?;
-			monitor.last_display_time = Instant::now();
+/// Prints the power of a single sample of the collection interval, for scripts.
+fn measure_once(cpu_type: &CpuType, options: &Options, config: &MonitorConfig) -> io::Result<()> {
+	let source = EnergySource::detect(cpu_type)?;
+	if let Some(note) = source.note() {
+		eprintln!("{note}");
+	}
+	let mut topology = CpuTopology::detect();
+	topology.detect_core_types(cpu_type);
+	let physical_cores = topology.physical_cores();
+	let config = MonitorConfig {
+		averaging_window: 1,
+		..*config
+	};
+	let mut monitor = PowerMonitor::with_config(physical_cores, &config);
+	monitor.calibrate(cpu_type, &source)?;
+	let sample = measure_power_sample(cpu_type, &topology, &source, config.collection_interval())?;
+	monitor.update_readings(&sample)?;
+	let reading = monitor.calculate_averages()?;
+
+	if let Some(path) = &options.csv_out {
+		CsvLogger::open(path, physical_cores)?.write_reading(&reading)?;
+	}
+	let mut stdout = io::stdout();
+	if options.json {
+		return display::write_json_reading(&mut stdout, &reading);
+	}
+	writeln!(stdout, "Package: {:.2} W", reading.package)?;
+	for (core_id, power) in reading.cores.iter().enumerate() {
+		if let Some(watts) = power {
+			writeln!(stdout, "Core {core_id}: {watts:.2} W")?;
 		}
 	}
+	Ok(())
 }
 
 fn main() -> io::Result<()> {
+	let options = parse_args()?;
+	if options.command == Command::Help {
+		println!("{USAGE}");
+		return Ok(());
+	}
+	let config = options.monitor_config();
+	if options.command == Command::Query {
+		let path = options.socket.unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET_PATH));
+		println!("{}", query_reading(&path)?);
+		return Ok(());
+	}
+	#[cfg(feature = "serde")]
+	if let Some(path) = &options.replay {
+		return replay_recording(path, &options, &config);
+	}
 	let cpu_type = detect_cpu_type();
-	match cpu_type {
-		CpuType::Intel => {
-			println!("Intel CPU detected.");
-			monitor_cpu_power(&cpu_type)
-		},
-		CpuType::Amd => {
-			println!("AMD CPU detected.");
-			monitor_cpu_power(&cpu_type)
-		},
-		CpuType::Unsupported => {
-			eprintln!("Unsupported CPU type or unable to detect CPU type.");
-			std::process::exit(1);
-		},
+	if options.command == Command::Info {
+		let info = HardwareInfo::collect(cpu_type, options.tdp_watts);
+		if options.json {
+			println!("{}", info.to_json());
+		} else {
+			println!("{info}");
+		}
+		return Ok(());
+	}
+
+	if !options.trust_vm_rapl && running_in_vm() {
+		eprintln!("Running in VM: RAPL values may reflect host totals or be synthetic.");
+	}
+	if cpu_type == CpuType::Unsupported {
+		eprintln!("Unsupported CPU type or unable to detect CPU type.");
+		std::process::exit(1);
+	}
+	if options.once {
+		return measure_once(&cpu_type, &options, &config);
+	}
+	if let Command::Bench(argv) = &options.command {
+		return run_bench(&cpu_type, argv, &options);
+	}
+	if options.command == Command::Daemon {
+		return run_daemon(&cpu_type, &options, &config);
+	}
+	if let Some(pid) = options.profile_pid {
+		return profile_process(&cpu_type, pid, &config);
+	}
+	if options.output_mode() == OutputMode::Terminal {
+		println!("{} CPU detected.", cpu_type.as_str());
+	} else {
+		eprintln!("{} CPU detected.", cpu_type.as_str());
+	}
+	#[cfg(feature = "tui")]
+	if options.tui {
+		return monitor_cpu_power_tui(&cpu_type, &options, &config);
 	}
+	monitor_cpu_power(&cpu_type, &options, &config)
 }
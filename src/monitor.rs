@@ -0,0 +1,797 @@
+use crate::energy::{EnergySnapshot, EnergySource, PowerSample, measure_power_sample, read_energy_snapshot};
+use crate::info::TdpSource;
+use crate::power::{EnergyBudget, PowerReading};
+use crate::stats::{EnergyDelayProduct, linear_regression, percentile};
+use crate::topology::CpuTopology;
+use crate::{CoreType, CpuType, MonitorConfig, check_msr_latency};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::time::{Duration, Instant};
+
+const SAMPLE_INTERVAL_HISTORY: usize = 100;
+const TREND_SAMPLES: usize = 20;
+
+/// Called with the averaged reading when package power is above an alert threshold.
+pub type ThresholdAlert = Box<dyn Fn(&PowerReading) + Send>;
+
+/// How samples are smoothed into the readings of [`PowerMonitor::peek_latest_reading`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AveragingMode {
+	/// Mean of the last N samples, which lags power changes by the whole window
+	Window(usize),
+	/// Exponential moving average giving the newest sample this weight, from 0.0 to 1.0. Follows
+	/// power spikes faster than a window with similar smoothing.
+	Exponential(f64),
+}
+
+/// All-time `(minimum, maximum)` power in mW and when each occurred.
+pub type PowerExtremes = (Option<(f64, Instant)>, Option<(f64, Instant)>);
+
+fn update_extremes(extremes: &mut PowerExtremes, power_mw: f64, at: Instant) {
+	if extremes.0.is_none_or(|(min, _)| power_mw < min) {
+		extremes.0 = Some((power_mw, at));
+	}
+	if extremes.1.is_none_or(|(max, _)| power_mw > max) {
+		extremes.1 = Some((power_mw, at));
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct DomainTotals {
+	samples: u64,
+	avg_watts: f64,
+	/// Sum of squared deviations from the mean, for Welford's online variance
+	m2_watts: f64,
+	peak_watts: f64,
+	energy_joules: f64,
+}
+
+impl DomainTotals {
+	fn record(&mut self, power_mw: f64, interval: Duration) {
+		let watts = power_mw / 1000.0;
+		self.samples += 1;
+		let deviation = watts - self.avg_watts;
+		self.avg_watts += deviation / self.samples as f64;
+		self.m2_watts += deviation * (watts - self.avg_watts);
+		self.peak_watts = self.peak_watts.max(watts);
+		self.energy_joules += watts * interval.as_secs_f64();
+	}
+
+	/// Sample standard deviation, or 0 with fewer than two samples.
+	fn stddev_watts(&self) -> f64 {
+		if self.samples < 2 {
+			return 0.0;
+		}
+		(self.m2_watts / (self.samples - 1) as f64).sqrt()
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+	pub started_at: Instant,
+	pub elapsed: Duration,
+	pub pkg_avg_watts: f64,
+	pub pkg_peak_watts: f64,
+	pub total_energy_joules: f64,
+	pub core_summaries: HashMap<usize, CoreSummary>,
+	/// Relative accuracy of the readings as found by calibration, from 0 to 1. `None` while
+	/// calibration only checks how fast the MSRs can be read.
+	pub calibration_accuracy: Option<f64>,
+}
+
+impl SessionSummary {
+	/// Energy-delay product of the session, or `None` before any time has elapsed.
+	#[must_use]
+	pub fn edp(&self) -> Option<EnergyDelayProduct> {
+		let duration_s = self.elapsed.as_secs_f64();
+		(duration_s > 0.0).then(|| EnergyDelayProduct::new(self.total_energy_joules, duration_s))
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CoreSummary {
+	pub avg_watts: f64,
+	pub peak_watts: f64,
+	/// Type of the core on a hybrid CPU, `None` on others
+	pub core_type: Option<CoreType>,
+	pub energy_joules: f64,
+}
+
+/// Lifecycle of a [`PowerMonitor`]: `Unconfigured` after `new()`, then `Calibrating` and
+/// `Measuring` through `calibrate()`, `Paused` and back through `pause()`/`resume()` and
+/// `Finished` after `stop()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonitorState {
+	Unconfigured,
+	Calibrating,
+	Measuring,
+	Paused,
+	Finished,
+}
+
+pub struct PowerMonitor {
+	state: MonitorState,
+	averaging: AveragingMode,
+	window_size: usize,
+	collection_interval: Duration,
+	topology_refresh_interval: Option<Duration>,
+	/// Topology that changed during `monitor_with_callback` and was not taken yet
+	topology_update: Option<CpuTopology>,
+	power_readings: VecDeque<f64>,
+	core_power_readings: Vec<VecDeque<Option<f64>>>,
+	pp0_readings: VecDeque<f64>,
+	pp1_readings: VecDeque<f64>,
+	dram_readings: VecDeque<f64>,
+	platform_readings: VecDeque<f64>,
+	socket_readings: HashMap<usize, VecDeque<f64>>,
+	c0_readings: VecDeque<f64>,
+	/// Exponential moving averages in mW of the package and of each core by id
+	ema_package: Option<f64>,
+	ema_state: HashMap<usize, f64>,
+	/// Longer history of package and valid core samples for percentiles
+	stats_history_size: usize,
+	package_history: VecDeque<f64>,
+	core_history: Vec<VecDeque<f64>>,
+	started_at: Instant,
+	package_totals: DomainTotals,
+	core_totals: Vec<DomainTotals>,
+	pub pkg_power_extremes: PowerExtremes,
+	pub core_power_extremes: HashMap<usize, PowerExtremes>,
+	sample_intervals: VecDeque<Duration>,
+	pub tdp: Option<TdpSource>,
+	msr_error_count: u64,
+	last_msr_error: Option<(Instant, io::Error)>,
+	msr_error_kinds: HashMap<io::ErrorKind, u64>,
+	/// Recent `(seconds since start, package watts)` pairs for trend projection
+	trend_samples: VecDeque<(f64, f64)>,
+	last_sample_quantization_limited: bool,
+	last_sample_cores_estimated: bool,
+	last_sample_temperatures: HashMap<usize, u8>,
+	last_sample_frequencies: HashMap<usize, u32>,
+	last_sample_energy_perf_preferences: HashMap<usize, u8>,
+	last_sample_core_types: HashMap<usize, CoreType>,
+	last_sample_offline_cores: HashSet<usize>,
+	/// Package watts above which `threshold_alert` is called
+	threshold_watts: f64,
+	threshold_alert: Option<ThresholdAlert>,
+	/// Whether `threshold_alert` is called on every reading above the threshold, or only on the
+	/// first after power was at or below it
+	threshold_alert_repeats: bool,
+	threshold_exceeded: bool,
+	energy_budget_joules: Option<f64>,
+	budget_alert: Option<ThresholdAlert>,
+	budget_exhausted: bool,
+	confidence: f64,
+}
+
+impl PowerMonitor {
+	/// `window_size` is the number of samples averaged into each reading, at least 1. Samples are
+	/// taken at the default interval of [`MonitorConfig`].
+	#[must_use]
+	pub fn new(physical_cores: usize, window_size: usize) -> Self {
+		let window_size = window_size.max(1);
+		Self {
+			window_size,
+			collection_interval: MonitorConfig::default().collection_interval(),
+			topology_refresh_interval: MonitorConfig::default().topology_refresh_interval(),
+			topology_update: None,
+			averaging: AveragingMode::Window(window_size),
+			state: MonitorState::Unconfigured,
+			power_readings: VecDeque::with_capacity(window_size),
+			core_power_readings: vec![VecDeque::with_capacity(window_size); physical_cores],
+			pp0_readings: VecDeque::with_capacity(window_size),
+			pp1_readings: VecDeque::with_capacity(window_size),
+			dram_readings: VecDeque::with_capacity(window_size),
+			platform_readings: VecDeque::with_capacity(window_size),
+			socket_readings: HashMap::new(),
+			c0_readings: VecDeque::with_capacity(window_size),
+			ema_package: None,
+			ema_state: HashMap::new(),
+			stats_history_size: window_size,
+			package_history: VecDeque::with_capacity(window_size),
+			core_history: vec![VecDeque::with_capacity(window_size); physical_cores],
+			started_at: Instant::now(),
+			package_totals: DomainTotals::default(),
+			core_totals: vec![DomainTotals::default(); physical_cores],
+			pkg_power_extremes: (None, None),
+			core_power_extremes: HashMap::new(),
+			sample_intervals: VecDeque::with_capacity(SAMPLE_INTERVAL_HISTORY),
+			tdp: None,
+			msr_error_count: 0,
+			last_msr_error: None,
+			msr_error_kinds: HashMap::new(),
+			trend_samples: VecDeque::with_capacity(TREND_SAMPLES),
+			last_sample_quantization_limited: false,
+			last_sample_cores_estimated: false,
+			last_sample_temperatures: HashMap::new(),
+			last_sample_frequencies: HashMap::new(),
+			last_sample_energy_perf_preferences: HashMap::new(),
+			last_sample_core_types: HashMap::new(),
+			last_sample_offline_cores: HashSet::new(),
+			threshold_watts: f64::INFINITY,
+			threshold_alert: None,
+			threshold_alert_repeats: true,
+			threshold_exceeded: false,
+			energy_budget_joules: None,
+			budget_alert: None,
+			budget_exhausted: false,
+			confidence: MonitorConfig::default().confidence,
+		}
+	}
+
+	/// Monitor averaging `config.averaging_window` samples of `config.collection_interval_ms`.
+	#[must_use]
+	pub fn with_config(physical_cores: usize, config: &MonitorConfig) -> Self {
+		let mut monitor = Self::new(physical_cores, config.averaging_window);
+		monitor.collection_interval = config.collection_interval();
+		monitor.topology_refresh_interval = config.topology_refresh_interval();
+		monitor.confidence = config.confidence;
+		monitor
+	}
+
+	/// Starts the session over: clears the averaging windows, the statistics history, the power
+	/// extremes and the session totals, as if no sample had been recorded yet. Alerts that already
+	/// fired stay quiet.
+	pub fn reset_statistics(&mut self) {
+		self.power_readings.clear();
+		self.core_power_readings.iter_mut().for_each(VecDeque::clear);
+		self.pp0_readings.clear();
+		self.pp1_readings.clear();
+		self.dram_readings.clear();
+		self.platform_readings.clear();
+		self.socket_readings.clear();
+		self.c0_readings.clear();
+		self.ema_package = None;
+		self.ema_state.clear();
+		self.package_history.clear();
+		self.core_history.iter_mut().for_each(VecDeque::clear);
+		self.started_at = Instant::now();
+		self.package_totals = DomainTotals::default();
+		self.core_totals.fill(DomainTotals::default());
+		self.pkg_power_extremes = (None, None);
+		self.core_power_extremes.clear();
+		self.sample_intervals.clear();
+		self.trend_samples.clear();
+	}
+
+	/// Length of each sample taken by `monitor_with_callback`.
+	#[must_use]
+	pub const fn collection_interval(&self) -> Duration {
+		self.collection_interval
+	}
+
+	#[must_use]
+	pub const fn state(&self) -> MonitorState {
+		self.state
+	}
+
+	fn require_state(&self, allowed: &[MonitorState], action: &str) -> io::Result<()> {
+		if allowed.contains(&self.state) {
+			return Ok(());
+		}
+		Err(io::Error::other(format!(
+			"Cannot {action} while the monitor is {:?}, expected one of {allowed:?}",
+			self.state
+		)))
+	}
+
+	/// Checks that the MSRs can be read fast enough for the sampling interval, then starts
+	/// measuring. Powercap sources need no check.
+	pub fn calibrate(&mut self, cpu_type: &CpuType, source: &EnergySource) -> io::Result<()> {
+		self.require_state(&[MonitorState::Unconfigured], "calibrate")?;
+		self.state = MonitorState::Calibrating;
+		let latency_check = match source {
+			EnergySource::Msr { .. } => check_msr_latency(cpu_type, self.core_totals.len(), self.collection_interval),
+			EnergySource::Perf(_) | EnergySource::Powercap(_) => Ok(()),
+		};
+		if let Err(e) = latency_check {
+			self.state = MonitorState::Unconfigured;
+			return Err(e);
+		}
+		self.state = MonitorState::Measuring;
+		Ok(())
+	}
+
+	/// Stops recording samples until `resume()`. Averages and statistics are kept.
+	pub fn pause(&mut self) -> io::Result<()> {
+		self.require_state(&[MonitorState::Measuring], "pause")?;
+		self.state = MonitorState::Paused;
+		Ok(())
+	}
+
+	pub fn resume(&mut self) -> io::Result<()> {
+		self.require_state(&[MonitorState::Paused], "resume")?;
+		self.state = MonitorState::Measuring;
+		Ok(())
+	}
+
+	/// Ends monitoring. `monitor_with_callback` returns after the current callback.
+	pub fn stop(&mut self) {
+		self.state = MonitorState::Finished;
+	}
+
+	/// Switches how readings are smoothed. A window replaces the one given to `new()`, while an
+	/// exponential average starts over from the next sample. Domains other than the package and
+	/// the cores are always averaged over the window.
+	pub fn set_averaging_mode(&mut self, mode: AveragingMode) {
+		self.averaging = match mode {
+			AveragingMode::Window(window_size) => {
+				self.window_size = window_size.max(1);
+				AveragingMode::Window(self.window_size)
+			},
+			AveragingMode::Exponential(alpha) => AveragingMode::Exponential(alpha.clamp(0.0, 1.0)),
+		};
+		self.ema_package = None;
+		self.ema_state.clear();
+	}
+
+	/// Keeps the last `samples` package and core samples for [`Self::percentile_reading`] instead
+	/// of one window's worth.
+	pub fn set_stats_history(&mut self, samples: usize) {
+		self.stats_history_size = samples.max(1);
+		for history in std::iter::once(&mut self.package_history).chain(&mut self.core_history) {
+			while history.len() > self.stats_history_size {
+				history.pop_front();
+			}
+		}
+	}
+
+	/// Registers `on_exceed` to be called by `update_readings` whenever the averaged package power
+	/// is above `threshold_watts`. Replaces any earlier alert.
+	pub fn set_threshold_alert(&mut self, threshold_watts: f64, on_exceed: impl Fn(&PowerReading) + Send + 'static) {
+		self.threshold_watts = threshold_watts;
+		self.threshold_alert = Some(Box::new(on_exceed));
+		self.threshold_exceeded = false;
+	}
+
+	/// With `repeat` false, the threshold alert is only called when power rises above the
+	/// threshold, and again once it dropped back to or below it first, so a sustained excess is
+	/// reported once rather than on every sample.
+	pub const fn set_threshold_alert_repeat(&mut self, repeat: bool) {
+		self.threshold_alert_repeats = repeat;
+	}
+
+	/// Sets the package energy the session may use. Readings then carry an [`EnergyBudget`], and
+	/// the alert of `set_budget_alert` is called once the session energy reaches it.
+	pub fn set_energy_budget_joules(&mut self, joules: f64) {
+		self.energy_budget_joules = Some(joules);
+		self.budget_exhausted = false;
+	}
+
+	/// Registers `on_exhausted` to be called by `update_readings`, once, when the session energy
+	/// reaches the budget of `set_energy_budget_joules`. Replaces any earlier alert.
+	pub fn set_budget_alert(&mut self, on_exhausted: impl Fn(&PowerReading) + Send + 'static) {
+		self.budget_alert = Some(Box::new(on_exhausted));
+	}
+
+	/// Records a power sample. Its interval is also used to accumulate session energy. Samples
+	/// taken while paused are dropped.
+	pub fn update_readings(&mut self, sample: &PowerSample) -> io::Result<()> {
+		self.require_state(&[MonitorState::Measuring, MonitorState::Paused], "record samples")?;
+		if self.state == MonitorState::Paused {
+			return Ok(());
+		}
+		// A core came online since the monitor was sized
+		if sample.cores.len() > self.core_power_readings.len() {
+			self.resize_cores(sample.cores.len());
+		}
+
+		let now = Instant::now();
+		for error in &sample.msr_errors {
+			self.msr_error_count += 1;
+			*self.msr_error_kinds.entry(error.kind).or_default() += 1;
+			self.last_msr_error = Some((now, io::Error::from(error)));
+		}
+
+		self.package_totals.record(sample.package, sample.interval);
+		update_extremes(&mut self.pkg_power_extremes, sample.package, now);
+
+		self.sample_intervals.push_back(sample.interval);
+		if self.sample_intervals.len() > SAMPLE_INTERVAL_HISTORY {
+			self.sample_intervals.pop_front();
+		}
+		self.last_sample_quantization_limited = sample.quantization_limited;
+		self.last_sample_cores_estimated = sample.cores_estimated;
+		self.last_sample_temperatures.clone_from(&sample.temperatures);
+		self.last_sample_frequencies.clone_from(&sample.frequencies);
+		self.last_sample_energy_perf_preferences
+			.clone_from(&sample.energy_perf_preferences);
+		self.last_sample_core_types.clone_from(&sample.core_types);
+		self.last_sample_offline_cores.clone_from(&sample.offline_cores);
+		let since_start = now.saturating_duration_since(self.started_at).as_secs_f64();
+		self.trend_samples.push_back((since_start, sample.package / 1000.0));
+		if self.trend_samples.len() > TREND_SAMPLES {
+			self.trend_samples.pop_front();
+		}
+		self.power_readings.push_back(sample.package);
+		if self.power_readings.len() > self.window_size {
+			self.power_readings.pop_front();
+		}
+		self.package_history.push_back(sample.package);
+		if self.package_history.len() > self.stats_history_size {
+			self.package_history.pop_front();
+		}
+
+		if let Some(pp0) = sample.pp0 {
+			self.pp0_readings.push_back(pp0);
+			if self.pp0_readings.len() > self.window_size {
+				self.pp0_readings.pop_front();
+			}
+		}
+
+		if let Some(pp1) = sample.pp1 {
+			self.pp1_readings.push_back(pp1);
+			if self.pp1_readings.len() > self.window_size {
+				self.pp1_readings.pop_front();
+			}
+		}
+
+		if let Some(dram) = sample.dram {
+			self.dram_readings.push_back(dram);
+			if self.dram_readings.len() > self.window_size {
+				self.dram_readings.pop_front();
+			}
+		}
+
+		if let Some(platform) = sample.platform {
+			self.platform_readings.push_back(platform);
+			if self.platform_readings.len() > self.window_size {
+				self.platform_readings.pop_front();
+			}
+		}
+
+		for (&socket, &power) in &sample.sockets {
+			let readings = self.socket_readings.entry(socket).or_default();
+			readings.push_back(power);
+			if readings.len() > self.window_size {
+				readings.pop_front();
+			}
+		}
+
+		if let Some(c0_fraction) = sample.c0_fraction {
+			self.c0_readings.push_back(c0_fraction);
+			if self.c0_readings.len() > self.window_size {
+				self.c0_readings.pop_front();
+			}
+		}
+
+		for (core_id, &power) in sample.cores.iter().enumerate() {
+			if let Some(power) = power {
+				self.core_totals[core_id].record(power, sample.interval);
+				update_extremes(self.core_power_extremes.entry(core_id).or_default(), power, now);
+				self.core_history[core_id].push_back(power);
+				if self.core_history[core_id].len() > self.stats_history_size {
+					self.core_history[core_id].pop_front();
+				}
+			}
+			self.core_power_readings[core_id].push_back(power);
+			if self.core_power_readings[core_id].len() > self.window_size {
+				self.core_power_readings[core_id].pop_front();
+			}
+		}
+
+		if let AveragingMode::Exponential(alpha) = self.averaging {
+			let smooth = |ema: Option<f64>, power: f64| ema.map_or(power, |ema| alpha * power + (1.0 - alpha) * ema);
+			self.ema_package = Some(smooth(self.ema_package, sample.package));
+			for (core_id, &power) in sample.cores.iter().enumerate() {
+				match power {
+					Some(power) => {
+						let ema = smooth(self.ema_state.get(&core_id).copied(), power);
+						self.ema_state.insert(core_id, ema);
+					},
+					// An offline core starts over once it is back
+					None => {
+						self.ema_state.remove(&core_id);
+					},
+				}
+			}
+		}
+
+		if let Some(on_exceed) = &self.threshold_alert {
+			if let Some(reading) = self.peek_latest_reading() {
+				let exceeded = reading.package > self.threshold_watts;
+				if exceeded && (self.threshold_alert_repeats || !self.threshold_exceeded) {
+					on_exceed(&reading);
+				}
+				self.threshold_exceeded = exceeded;
+			}
+		}
+
+		if !self.budget_exhausted {
+			if let Some(reading) = self.peek_latest_reading() {
+				if reading.energy_budget.is_some_and(|budget| budget.is_exhausted()) {
+					self.budget_exhausted = true;
+					if let Some(on_exhausted) = &self.budget_alert {
+						on_exhausted(&reading);
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Keeps the per-core windows and session statistics of the first `physical_cores` cores and
+	/// adds empty ones up to it.
+	fn resize_cores(&mut self, physical_cores: usize) {
+		self.core_power_readings
+			.resize(physical_cores, VecDeque::with_capacity(self.window_size));
+		self.core_history.resize(physical_cores, VecDeque::new());
+		self.core_totals.resize(physical_cores, DomainTotals::default());
+		self.ema_state.retain(|&core_id, _| core_id < physical_cores);
+		self.core_power_extremes.retain(|&core_id, _| core_id < physical_cores);
+	}
+
+	/// The topology `monitor_with_callback` switched to after CPUs were hotplugged, once per
+	/// change, so the callback can lay out its display again.
+	pub const fn take_topology_update(&mut self) -> Option<CpuTopology> {
+		self.topology_update.take()
+	}
+
+	/// Samples power every collection interval until the monitor is stopped or the
+	/// callback or a measurement fails. The callback runs after each sample with the monitor and
+	/// its averaged reading, so it can drive a display, a log or alerts. While paused it still
+	/// runs with the last reading.
+	///
+	/// The callback gets `&mut Self` rather than the reading alone because the loop holds the
+	/// only mutable borrow of the monitor: it is the callback's way to `stop()` or `pause()` the
+	/// session, pick up a hotplug with [`Self::take_topology_update`] or read the session totals.
+	///
+	/// The topology is re-read every topology refresh interval of the [`MonitorConfig`], and
+	/// CPUs that were hotplugged are sampled from then on.
+	///
+	/// The monitor must have been calibrated.
+	pub fn monitor_with_callback<F>(
+		&mut self,
+		cpu_type: &CpuType,
+		topology: &CpuTopology,
+		source: &EnergySource,
+		mut callback: F,
+	) -> io::Result<()>
+	where
+		F: FnMut(&mut Self, &PowerReading) -> io::Result<()>,
+	{
+		self.require_state(&[MonitorState::Measuring, MonitorState::Paused], "monitor")?;
+		let mut topology = topology.clone();
+		let mut last_topology_refresh = Instant::now();
+		loop {
+			if self
+				.topology_refresh_interval
+				.is_some_and(|interval| last_topology_refresh.elapsed() >= interval)
+			{
+				last_topology_refresh = Instant::now();
+				let (had_ccx, had_preferred, had_core_types) = (
+					!topology.core_to_ccx.is_empty(),
+					!topology.preferred_cores.is_empty(),
+					!topology.core_types.is_empty(),
+				);
+				// A failed read keeps the current layout until the next refresh
+				if topology.refresh().unwrap_or(false) {
+					if had_ccx {
+						topology.detect_ccx(cpu_type);
+					}
+					if had_preferred {
+						topology.detect_preferred_cores(cpu_type);
+					}
+					if had_core_types {
+						topology.detect_core_types(cpu_type);
+					}
+					self.resize_cores(topology.physical_cores());
+					self.topology_update = Some(topology.clone());
+				}
+			}
+			let sample = measure_power_sample(cpu_type, &topology, source, self.collection_interval)?;
+			self.update_readings(&sample)?;
+			if let Some(reading) = self.peek_latest_reading() {
+				callback(self, &reading)?;
+			}
+			if self.state == MonitorState::Finished {
+				return Ok(());
+			}
+		}
+	}
+
+	/// Projects when package power reaches `limit_watts` if the linear trend of the last
+	/// `TREND_SAMPLES` samples continues. `None` while power is flat, falling or already above the
+	/// limit.
+	#[must_use]
+	pub fn time_to_power_limit(&self, limit_watts: f64) -> Option<Duration> {
+		let &(latest_s, latest_watts) = self.trend_samples.back()?;
+		if self.trend_samples.len() < TREND_SAMPLES || latest_watts >= limit_watts {
+			return None;
+		}
+		let (x, y): (Vec<f64>, Vec<f64>) = self.trend_samples.iter().copied().unzip();
+		let (slope, intercept) = linear_regression(&x, &y);
+		if slope <= 0.0 {
+			return None;
+		}
+		let eta_s = (limit_watts - intercept) / slope - latest_s;
+		(eta_s > 0.0).then(|| Duration::from_secs_f64(eta_s))
+	}
+
+	/// Number of MSR reads that failed during the session without aborting a sample.
+	#[must_use]
+	pub const fn msr_error_count(&self) -> u64 {
+		self.msr_error_count
+	}
+
+	#[must_use]
+	pub const fn last_msr_error(&self) -> Option<&(Instant, io::Error)> {
+		self.last_msr_error.as_ref()
+	}
+
+	/// Failed MSR reads of the session by error kind, most frequent first.
+	#[must_use]
+	pub fn msr_error_report(&self) -> Vec<(io::ErrorKind, u64)> {
+		let mut report: Vec<(io::ErrorKind, u64)> = self
+			.msr_error_kinds
+			.iter()
+			.map(|(&kind, &count)| (kind, count))
+			.collect();
+		report.sort_by_key(|&(_, count)| Reverse(count));
+		report
+	}
+
+	/// Reads the raw energy counters without recording anything, for callers that compute power
+	/// their own way. Pair two snapshots with [`EnergySnapshot::diff`].
+	pub fn take_snapshot(&self, cpu_type: &CpuType, topology: &CpuTopology) -> io::Result<EnergySnapshot> {
+		read_energy_snapshot(cpu_type, topology)
+	}
+
+	/// Package power in mW and interval of the most recent sample, before averaging.
+	#[must_use]
+	pub fn last_package_sample(&self) -> Option<(f64, Duration)> {
+		Some((*self.power_readings.back()?, *self.sample_intervals.back()?))
+	}
+
+	/// Returns the averaged reading, or `None` before the first sample has been recorded.
+	#[must_use]
+	pub fn peek_latest_reading(&self) -> Option<PowerReading> {
+		if self.power_readings.is_empty() {
+			return None;
+		}
+		match self.averaging {
+			AveragingMode::Window(_) => self.calculate_averages().ok(),
+			AveragingMode::Exponential(_) => self.calculate_ema_reading().ok(),
+		}
+	}
+
+	/// Like [`Self::calculate_averages`], but with the package and core powers taken from the
+	/// exponential moving averages. Only differs once [`AveragingMode::Exponential`] is set.
+	#[must_use = "this reading should be displayed or stored"]
+	pub fn calculate_ema_reading(&self) -> io::Result<PowerReading> {
+		let mut reading = self.calculate_averages()?;
+		let Some(package) = self.ema_package else {
+			return Ok(reading);
+		};
+		reading.package = package / 1000.0;
+		for (core_id, power) in reading.cores.iter_mut().enumerate() {
+			*power = self.ema_state.get(&core_id).map(|ema| ema / 1000.0);
+		}
+		Ok(reading)
+	}
+
+	/// Averages the window of recent samples. Fails before the monitor has been calibrated.
+	#[must_use = "this reading should be displayed or stored"]
+	pub fn calculate_averages(&self) -> io::Result<PowerReading> {
+		self.require_state(
+			&[MonitorState::Measuring, MonitorState::Paused, MonitorState::Finished],
+			"calculate averages",
+		)?;
+		let package_avg = self.calculate_average_power(&self.power_readings);
+		let cores: Vec<Option<f64>> = self
+			.core_power_readings
+			.iter()
+			.map(|readings| self.calculate_average_core_power(readings))
+			.collect();
+
+		let pp0_avg = (!self.pp0_readings.is_empty()).then(|| self.calculate_average_power(&self.pp0_readings));
+		let pp1_avg = (!self.pp1_readings.is_empty()).then(|| self.calculate_average_power(&self.pp1_readings));
+		let dram_avg = (!self.dram_readings.is_empty()).then(|| self.calculate_average_power(&self.dram_readings));
+		let platform_avg =
+			(!self.platform_readings.is_empty()).then(|| self.calculate_average_power(&self.platform_readings));
+		let sockets = self
+			.socket_readings
+			.iter()
+			.map(|(&socket, readings)| (socket, self.calculate_average_power(readings)))
+			.collect();
+
+		Ok(PowerReading {
+			package: package_avg,
+			pkg_stddev_watts: self.package_totals.stddev_watts(),
+			quantization_limited: self.last_sample_quantization_limited,
+			cores_estimated: self.last_sample_cores_estimated,
+			temperatures: self.last_sample_temperatures.clone(),
+			frequencies: self.last_sample_frequencies.clone(),
+			energy_perf_preferences: self.last_sample_energy_perf_preferences.clone(),
+			core_types: self.last_sample_core_types.clone(),
+			offline_cores: self.last_sample_offline_cores.clone(),
+			c0_fraction: (!self.c0_readings.is_empty())
+				.then(|| self.c0_readings.iter().sum::<f64>() / self.c0_readings.len() as f64),
+			cores,
+			pp0_watts: pp0_avg,
+			pp1_watts: pp1_avg,
+			dram_watts: dram_avg,
+			platform_watts: platform_avg,
+			sockets,
+			energy_budget: self.energy_budget_joules.map(|budget_joules| EnergyBudget {
+				used_joules: self.package_totals.energy_joules,
+				budget_joules,
+			}),
+			confidence: self.confidence,
+		})
+	}
+
+	/// The `p`-th percentile (0 to 100) of package and core power over the stats history, with
+	/// the other domains averaged as in [`Self::calculate_averages`]. Sorts every history, so call
+	/// it for summaries rather than on every sample.
+	#[must_use = "this reading should be displayed or stored"]
+	pub fn percentile_reading(&self, p: f64) -> io::Result<PowerReading> {
+		let mut reading = self.calculate_averages()?;
+		reading.package = percentile(self.package_history.iter().copied(), p).unwrap_or(0.0) / 1000.0;
+		reading.cores = self
+			.core_history
+			.iter()
+			.map(|history| percentile(history.iter().copied(), p).map(|power| power / 1000.0))
+			.collect();
+		Ok(reading)
+	}
+
+	fn calculate_average_power(&self, readings: &VecDeque<f64>) -> f64 {
+		let total: f64 = readings.iter().sum();
+		total / readings.len() as f64 / 1000.0
+	}
+
+	/// Averages the valid samples of a core, or returns `None` when the core was offline for more
+	/// than half of the window.
+	fn calculate_average_core_power(&self, readings: &VecDeque<Option<f64>>) -> Option<f64> {
+		let valid: Vec<f64> = readings.iter().flatten().copied().collect();
+		if valid.is_empty() || valid.len() * 2 < readings.len() {
+			return None;
+		}
+		let total: f64 = valid.iter().sum();
+		Some(total / valid.len() as f64 / 1000.0)
+	}
+
+	/// Actual durations of the most recent samples, for spotting scheduling jitter.
+	#[must_use]
+	pub const fn sample_intervals(&self) -> &VecDeque<Duration> {
+		&self.sample_intervals
+	}
+
+	/// Package energy of every sample recorded since the monitor was created.
+	#[must_use]
+	pub const fn session_energy_joules(&self) -> f64 {
+		self.package_totals.energy_joules
+	}
+
+	/// Statistics over every sample recorded since the monitor was created.
+	#[must_use]
+	pub fn session_summary(&self) -> SessionSummary {
+		let core_summaries = self
+			.core_totals
+			.iter()
+			.enumerate()
+			.filter(|(_, totals)| totals.samples > 0)
+			.map(|(core_id, totals)| {
+				let summary = CoreSummary {
+					avg_watts: totals.avg_watts,
+					peak_watts: totals.peak_watts,
+					core_type: self.last_sample_core_types.get(&core_id).copied(),
+					energy_joules: totals.energy_joules,
+				};
+				(core_id, summary)
+			})
+			.collect();
+
+		SessionSummary {
+			started_at: self.started_at,
+			elapsed: self.started_at.elapsed(),
+			pkg_avg_watts: self.package_totals.avg_watts,
+			pkg_peak_watts: self.package_totals.peak_watts,
+			total_energy_joules: self.package_totals.energy_joules,
+			core_summaries,
+			calibration_accuracy: None,
+		}
+	}
+}
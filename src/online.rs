@@ -0,0 +1,47 @@
+use crate::topology::CpuTopology;
+use std::collections::HashSet;
+use std::{fs, io};
+
+const ONLINE_CPUS_PATH: &str = "/sys/devices/system/cpu/online";
+
+/// Logical CPUs currently online, from the kernel's cpulist.
+pub fn read_online_cpus() -> io::Result<HashSet<usize>> {
+	parse_cpu_list(&fs::read_to_string(ONLINE_CPUS_PATH)?)
+}
+
+/// Parses a cpulist such as `0-5,8-11` or `0,2,4`.
+pub fn parse_cpu_list(list: &str) -> io::Result<HashSet<usize>> {
+	let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid CPU list: {}", list.trim()));
+	let mut cpus = HashSet::new();
+	for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+		let (first, last) = range.split_once('-').unwrap_or((range, range));
+		let first: usize = first.parse().map_err(|_| invalid())?;
+		let last: usize = last.parse().map_err(|_| invalid())?;
+		if first > last {
+			return Err(invalid());
+		}
+		cpus.extend(first..=last);
+	}
+	Ok(cpus)
+}
+
+/// The threads of `threads` that are online. Without an online list every thread counts as online.
+#[must_use]
+pub fn online_threads(threads: &[usize], online: Option<&HashSet<usize>>) -> Vec<usize> {
+	threads
+		.iter()
+		.copied()
+		.filter(|cpu| online.is_none_or(|online| online.contains(cpu)))
+		.collect()
+}
+
+/// Physical cores none of whose threads are online.
+#[must_use]
+pub fn offline_cores(topology: &CpuTopology, online: &HashSet<usize>) -> HashSet<usize> {
+	topology
+		.core_to_threads
+		.iter()
+		.filter(|(_, threads)| online_threads(threads, Some(online)).is_empty())
+		.map(|(&core, _)| core)
+		.collect()
+}
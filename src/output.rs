@@ -0,0 +1,3 @@
+pub mod csv;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
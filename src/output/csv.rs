@@ -0,0 +1,74 @@
+use crate::power::PowerReading;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_FLUSH_EVERY: usize = 10;
+
+/// Appends one CSV row per reading: `unix_timestamp_ms,package_watts,estimated,core0,core1,…`.
+/// Cores without a reading are left empty.
+pub struct CsvLogger {
+	writer: BufWriter<File>,
+	flush_every: usize,
+	pending_rows: usize,
+}
+
+impl CsvLogger {
+	/// Opens `path` for appending. The header is only written when the file is new or empty, so
+	/// consecutive sessions can share a file.
+	pub fn open(path: impl AsRef<Path>, physical_cores: usize) -> io::Result<Self> {
+		let file = OpenOptions::new().create(true).append(true).open(path)?;
+		let is_empty = file.metadata()?.len() == 0;
+		let mut writer = BufWriter::new(file);
+
+		if is_empty {
+			write!(writer, "unix_timestamp_ms,package_watts,estimated")?;
+			for core_id in 0..physical_cores {
+				write!(writer, ",core{core_id}")?;
+			}
+			writeln!(writer)?;
+			writer.flush()?;
+		}
+
+		Ok(Self {
+			writer,
+			flush_every: DEFAULT_FLUSH_EVERY,
+			pending_rows: 0,
+		})
+	}
+
+	/// Flushes after every `rows` rows instead of every 10. Fewer flushes mean less I/O but more
+	/// rows lost on a crash.
+	#[must_use]
+	pub fn with_flush_every(mut self, rows: usize) -> Self {
+		self.flush_every = rows.max(1);
+		self
+	}
+
+	pub fn write_reading(&mut self, reading: &PowerReading) -> io::Result<()> {
+		let timestamp_ms = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_millis();
+		write!(
+			self.writer,
+			"{timestamp_ms},{:.3},{}",
+			reading.package, reading.cores_estimated
+		)?;
+		for power in &reading.cores {
+			match power {
+				Some(watts) => write!(self.writer, ",{watts:.3}")?,
+				None => write!(self.writer, ",")?,
+			}
+		}
+		writeln!(self.writer)?;
+
+		self.pending_rows += 1;
+		if self.pending_rows >= self.flush_every {
+			self.writer.flush()?;
+			self.pending_rows = 0;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,125 @@
+use crate::info::escape_json;
+use crate::power::PowerReading;
+use crate::stats::format_rfc3339;
+use rumqttc::{Client, ClientError, Event, MqttOptions, Packet, QoS};
+use std::time::{Duration, SystemTime};
+use std::{io, thread};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Publishes queued while the broker is unreachable, beyond which readings are dropped
+const REQUEST_QUEUE_LENGTH: usize = 256;
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Publishes readings to an MQTT broker, the package power to `<topic>/package` and each core to
+/// `<topic>/cores/<id>`, for home automation or energy dashboards.
+///
+/// A background thread drives the connection: it sends keepalive pings and, when the broker
+/// goes away, reconnects after a delay that doubles from 1 s up to 60 s and starts over once
+/// connected again.
+pub struct MqttPublisher {
+	client: Client,
+	topic: String,
+	qos: QoS,
+	retain: bool,
+	dropped_readings: u64,
+}
+
+impl MqttPublisher {
+	/// Starts connecting to `broker`, given as `host:port`. Publishing does not wait for the
+	/// connection, so an unreachable broker is not an error here.
+	pub fn connect(broker: &str, topic: &str, qos: QoS, retain: bool) -> io::Result<Self> {
+		let (host, port) = parse_broker(broker)?;
+		let mut options = MqttOptions::new(format!("cpu-power-{}", std::process::id()), host, port);
+		options.set_keep_alive(KEEP_ALIVE);
+		let (client, mut connection) = Client::new(options, REQUEST_QUEUE_LENGTH);
+
+		thread::spawn(move || {
+			let mut reconnect_delay = MIN_RECONNECT_DELAY;
+			// Ends once the client is dropped
+			for event in connection.iter() {
+				match event {
+					Ok(Event::Incoming(Packet::ConnAck(_))) => reconnect_delay = MIN_RECONNECT_DELAY,
+					Ok(_) => {},
+					Err(_) => {
+						thread::sleep(reconnect_delay);
+						reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+					},
+				}
+			}
+		});
+
+		Ok(Self {
+			client,
+			topic: topic.trim_end_matches('/').to_string(),
+			qos,
+			retain,
+			dropped_readings: 0,
+		})
+	}
+
+	/// Queues the package power as `{"timestamp":"…","watts":18.5,"estimated":false}` and each
+	/// core as `{"timestamp":"…","id":0,"watts":1.2}`, with `null` watts for cores without a
+	/// reading. While the queue is full the reading is dropped rather than holding up sampling.
+	pub fn publish(&mut self, reading: &PowerReading) -> io::Result<()> {
+		let timestamp = escape_json(&format_rfc3339(SystemTime::now()));
+		let mut messages = vec![(
+			format!("{}/package", self.topic),
+			format!(
+				"{{\"timestamp\":{timestamp},\"watts\":{},\"estimated\":{}}}",
+				reading.package, reading.cores_estimated
+			),
+		)];
+		messages.extend(reading.cores.iter().enumerate().map(|(id, watts)| {
+			let watts = watts.map_or_else(|| String::from("null"), |watts| watts.to_string());
+			(
+				format!("{}/cores/{id}", self.topic),
+				format!("{{\"timestamp\":{timestamp},\"id\":{id},\"watts\":{watts}}}"),
+			)
+		}));
+
+		for (topic, payload) in messages {
+			match self.client.try_publish(topic, self.qos, self.retain, payload) {
+				Ok(()) => {},
+				Err(ClientError::TryRequest(_)) => {
+					self.dropped_readings += 1;
+					return Ok(());
+				},
+				Err(e) => return Err(io::Error::other(e)),
+			}
+		}
+		Ok(())
+	}
+
+	/// Readings not published, in full or in part, because the queue was full.
+	#[must_use]
+	pub const fn dropped_readings(&self) -> u64 {
+		self.dropped_readings
+	}
+}
+
+/// QoS from its level, 0 (at most once), 1 (at least once) or 2 (exactly once).
+pub fn parse_qos(level: u8) -> io::Result<QoS> {
+	match level {
+		0 => Ok(QoS::AtMostOnce),
+		1 => Ok(QoS::AtLeastOnce),
+		2 => Ok(QoS::ExactlyOnce),
+		_ => Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("MQTT QoS must be 0, 1 or 2, not {level}"),
+		)),
+	}
+}
+
+fn parse_broker(broker: &str) -> io::Result<(&str, u16)> {
+	broker
+		.rsplit_once(':')
+		.and_then(|(host, port)| Some((host, port.parse().ok()?)))
+		.filter(|(host, _)| !host.is_empty())
+		.ok_or_else(|| {
+			io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("MQTT broker must be host:port, not {broker}"),
+			)
+		})
+}
@@ -0,0 +1,220 @@
+use crate::energy::PowerSample;
+use crate::online::parse_cpu_list;
+use crate::powercap::PowercapDomain;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::FromRawFd;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{mem, thread};
+
+const POWER_PMU_PATH: &str = "/sys/bus/event_source/devices/power";
+/// `PERF_FLAG_FD_CLOEXEC`
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+/// RAPL events of the `power` PMU and the domain each counts
+const RAPL_EVENTS: &[(&str, PowercapDomain)] = &[
+	("energy-pkg", PowercapDomain::Package),
+	("energy-cores", PowercapDomain::Core),
+	("energy-gpu", PowercapDomain::Uncore),
+	("energy-ram", PowercapDomain::Dram),
+	("energy-psys", PowercapDomain::Platform),
+];
+
+/// `struct perf_event_attr` up to `PERF_ATTR_SIZE_VER0`, which is all a counting event or a
+/// plain sampling event needs. The kernel zero-extends older layouts.
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct PerfEventAttr {
+	pub(crate) kind: u32,
+	pub(crate) size: u32,
+	pub(crate) config: u64,
+	/// Events between samples, or samples per second with the `freq` flag
+	pub(crate) sample_period: u64,
+	pub(crate) sample_type: u64,
+	pub(crate) read_format: u64,
+	/// The bitfield from `disabled` (bit 0) on
+	pub(crate) flags: u64,
+	pub(crate) wakeup_events: u32,
+	pub(crate) bp_type: u32,
+	pub(crate) config1: u64,
+}
+
+impl PerfEventAttr {
+	pub(crate) fn new(kind: u32, config: u64) -> Self {
+		Self {
+			kind,
+			size: mem::size_of::<Self>() as u32,
+			config,
+			..Self::default()
+		}
+	}
+}
+
+/// Opens an event of `attr` for the task `pid` (-1 for every task) on `cpu` (-1 for every CPU).
+pub(crate) fn perf_event_open(attr: &PerfEventAttr, pid: libc::pid_t, cpu: libc::c_int) -> io::Result<File> {
+	// SAFETY: `attr` is a valid `perf_event_attr` prefix whose size field matches its length
+	let fd = unsafe {
+		libc::syscall(
+			libc::SYS_perf_event_open,
+			&raw const *attr,
+			pid,
+			cpu,
+			-1 as libc::c_int,
+			PERF_FLAG_FD_CLOEXEC,
+		)
+	};
+	if fd < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	// SAFETY: the syscall returned a new file descriptor that nothing else owns
+	Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
+
+#[derive(Debug, Clone)]
+struct PerfEvent {
+	domain: PowercapDomain,
+	file: Arc<File>,
+	/// Joules per count, from the event's `.scale` file
+	joules_per_count: f64,
+}
+
+impl PerfEvent {
+	/// Opens a system-wide counting event on `cpu`, as the `power` PMU has no per-task counters.
+	fn open(pmu_type: u32, config: u64, cpu: usize, domain: PowercapDomain, joules_per_count: f64) -> io::Result<Self> {
+		let cpu = libc::c_int::try_from(cpu).map_err(io::Error::other)?;
+		let file = perf_event_open(&PerfEventAttr::new(pmu_type, config), -1, cpu)?;
+		Ok(Self {
+			domain,
+			file: Arc::new(file),
+			joules_per_count,
+		})
+	}
+
+	/// The 64-bit count, which does not wrap in practice.
+	fn read_count(&self) -> io::Result<u64> {
+		let mut buf = [0u8; 8];
+		(&*self.file).read_exact(&mut buf)?;
+		Ok(u64::from_ne_bytes(buf))
+	}
+}
+
+/// RAPL counters of the kernel's `power` perf PMU. They need neither root nor the `msr` module,
+/// only a `perf_event_paranoid` of 0 or less or `CAP_PERFMON`, but like powercap they have no
+/// per-core readings.
+#[derive(Debug, Clone)]
+pub struct PerfRapl {
+	events: Vec<PerfEvent>,
+}
+
+impl PerfRapl {
+	/// Opens every RAPL event the PMU lists on the first CPU of each package. Fails unless the
+	/// package event can be opened.
+	pub fn open() -> io::Result<Self> {
+		let pmu = Path::new(POWER_PMU_PATH);
+		let pmu_type: u32 = parse_sysfs(&pmu.join("type"))?;
+		let package_cpus = parse_cpu_list(&fs::read_to_string(pmu.join("cpumask"))?)?;
+
+		let mut events = Vec::new();
+		for &(name, domain) in RAPL_EVENTS {
+			let Ok(config) = read_event_config(&pmu.join("events").join(name)) else {
+				continue;
+			};
+			let joules_per_count: f64 = parse_sysfs(&pmu.join("events").join(format!("{name}.scale")))?;
+			for &cpu in &package_cpus {
+				match PerfEvent::open(pmu_type, config, cpu, domain, joules_per_count) {
+					Ok(event) => events.push(event),
+					Err(e) if domain == PowercapDomain::Package => return Err(e),
+					Err(_) => {},
+				}
+			}
+		}
+
+		if !events.iter().any(|event| event.domain == PowercapDomain::Package) {
+			return Err(io::Error::new(
+				io::ErrorKind::NotFound,
+				format!("No energy-pkg event in {POWER_PMU_PATH}"),
+			));
+		}
+		Ok(Self { events })
+	}
+
+	fn read_counts(&self) -> io::Result<Vec<u64>> {
+		self.events.iter().map(PerfEvent::read_count).collect()
+	}
+
+	/// Measures the power of every domain over `duration`, summed over the packages. The core
+	/// domain is reported as a single core, as with powercap.
+	pub fn measure_power_sample(&self, duration: Duration) -> io::Result<PowerSample> {
+		let started_at = Instant::now();
+		let initial_counts = self.read_counts()?;
+		thread::sleep(duration);
+		let final_counts = self.read_counts()?;
+		let interval = started_at.elapsed();
+
+		let power_mw = |domain: PowercapDomain| {
+			let mut energy_joules = None;
+			for ((event, &start), &end) in self.events.iter().zip(&initial_counts).zip(&final_counts) {
+				if event.domain == domain {
+					let delta = end.wrapping_sub(start) as f64 * event.joules_per_count;
+					energy_joules = Some(energy_joules.unwrap_or(0.0) + delta);
+				}
+			}
+			energy_joules.map(|energy_joules| {
+				let elapsed_s = interval.as_secs_f64();
+				if elapsed_s == 0.0 {
+					0.0
+				} else {
+					energy_joules / elapsed_s * 1000.0
+				}
+			})
+		};
+
+		let package = power_mw(PowercapDomain::Package).unwrap_or(0.0);
+		let core = power_mw(PowercapDomain::Core);
+		Ok(PowerSample {
+			package,
+			cores: vec![core],
+			cores_estimated: false,
+			pp0: core,
+			pp1: power_mw(PowercapDomain::Uncore),
+			dram: power_mw(PowercapDomain::Dram),
+			platform: power_mw(PowercapDomain::Platform),
+			sockets: HashMap::new(),
+			interval,
+			msr_errors: Vec::new(),
+			quantization_limited: package == 0.0,
+			c0_fraction: None,
+			temperatures: HashMap::new(),
+			frequencies: HashMap::new(),
+			energy_perf_preferences: HashMap::new(),
+			core_types: HashMap::new(),
+			offline_cores: HashSet::new(),
+		})
+	}
+}
+
+/// The `config` of an event file such as `event=0x02`.
+fn read_event_config(path: &Path) -> io::Result<u64> {
+	let contents = fs::read_to_string(path)?;
+	contents
+		.trim()
+		.strip_prefix("event=")
+		.and_then(|event| u64::from_str_radix(event.trim_start_matches("0x"), 16).ok())
+		.ok_or_else(|| {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("Unsupported event in {}: {}", path.display(), contents.trim()),
+			)
+		})
+}
+
+fn parse_sysfs<T: std::str::FromStr>(path: &Path) -> io::Result<T> {
+	fs::read_to_string(path)?.trim().parse().map_err(|_| {
+		io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("Invalid value in {}", path.display()),
+		)
+	})
+}
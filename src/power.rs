@@ -0,0 +1,134 @@
+use crate::CoreType;
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+const VARIABLE_POWER_RATIO: f64 = 0.2;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PowerReading {
+	pub package: f64,
+	/// Standard deviation of package power over the session
+	pub pkg_stddev_watts: f64,
+	/// The latest sample was below the energy counter resolution
+	pub quantization_limited: bool,
+	/// Fraction of time the boot CPU was active (C0)
+	pub c0_fraction: Option<f64>,
+	pub cores: Vec<Option<f64>>,
+	/// Core powers are estimated from PP0 rather than measured per core
+	pub cores_estimated: bool,
+	/// Latest temperature in °C of each physical core with a readable sensor
+	pub temperatures: HashMap<usize, u8>,
+	/// Latest frequency in MHz of each physical core, averaged over its threads
+	pub frequencies: HashMap<usize, u32>,
+	/// Latest HWP energy-performance preference of each physical core, empty without HWP
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub energy_perf_preferences: HashMap<usize, u8>,
+	/// Type of each physical core, empty unless the CPU is hybrid
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub core_types: HashMap<usize, CoreType>,
+	/// Physical cores with no online thread in the latest sample
+	pub offline_cores: HashSet<usize>,
+	pub pp0_watts: Option<f64>,
+	pub pp1_watts: Option<f64>,
+	pub dram_watts: Option<f64>,
+	/// Intel PSys power of the whole platform: package, PCH, DRAM and VR losses
+	pub platform_watts: Option<f64>,
+	/// Package power of each socket
+	pub sockets: HashMap<usize, f64>,
+	/// Session energy against the budget, when one is set
+	pub energy_budget: Option<EnergyBudget>,
+	/// How far the counters can be trusted, from 0 to 1: 1 on bare metal and
+	/// [`VM_RAPL_CONFIDENCE`](crate::VM_RAPL_CONFIDENCE) under a hypervisor
+	#[cfg_attr(feature = "serde", serde(default = "full_confidence"))]
+	pub confidence: f64,
+}
+
+#[cfg(feature = "serde")]
+const fn full_confidence() -> f64 {
+	1.0
+}
+
+/// Package energy used during the session out of an energy budget.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnergyBudget {
+	pub used_joules: f64,
+	pub budget_joules: f64,
+}
+
+impl EnergyBudget {
+	/// Used share of the budget, above 1 once it is exceeded.
+	pub fn used_fraction(&self) -> f64 {
+		if self.budget_joules > 0.0 {
+			self.used_joules / self.budget_joules
+		} else {
+			1.0
+		}
+	}
+
+	pub fn is_exhausted(&self) -> bool {
+		self.used_joules >= self.budget_joules
+	}
+}
+
+impl PowerReading {
+	/// Package power not drawn by the cores (memory controller, LLC, ring), when the core domain is
+	/// known.
+	pub fn uncore_power_estimate(&self) -> Option<f64> {
+		self.pp0_watts.map(|pp0| (self.package - pp0).max(0.0))
+	}
+
+	/// Whether package power swings by more than `VARIABLE_POWER_RATIO` of its average, as in burst
+	/// workloads.
+	pub fn is_variable(&self) -> bool {
+		self.package > 0.0 && self.pkg_stddev_watts > self.package * VARIABLE_POWER_RATIO
+	}
+
+	/// Watts left before package power reaches the sustained PL1 limit.
+	pub fn pl1_headroom_watts(&self, pl1_watts: f64) -> f64 {
+		(pl1_watts - self.package).max(0.0)
+	}
+
+	/// Labels the reading, e.g. "before optimization", for later before/after comparisons.
+	pub fn annotate(self, label: impl Into<String>) -> AnnotatedPowerReading {
+		AnnotatedPowerReading {
+			reading: self,
+			label: label.into(),
+			captured_at: SystemTime::now(),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AnnotatedPowerReading {
+	pub reading: PowerReading,
+	pub label: String,
+	pub captured_at: SystemTime,
+}
+
+impl AnnotatedPowerReading {
+	/// Power of this reading minus the power of `other`. Cores without a reading on either side
+	/// have no delta.
+	pub fn compare(&self, other: &Self) -> PowerDelta {
+		let cores = self
+			.reading
+			.cores
+			.iter()
+			.zip(other.reading.cores.iter())
+			.map(|(&this, &other)| Some(this? - other?))
+			.collect();
+
+		PowerDelta {
+			package: self.reading.package - other.reading.package,
+			cores,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct PowerDelta {
+	pub package: f64,
+	pub cores: Vec<Option<f64>>,
+}
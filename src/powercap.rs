@@ -0,0 +1,205 @@
+use crate::energy::PowerSample;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{fs, io, thread};
+
+const POWERCAP_PATH: &str = "/sys/class/powercap";
+const RAPL_ZONE_PREFIX: &str = "intel-rapl:";
+
+/// RAPL domain of a powercap zone, from its `name` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowercapDomain {
+	Package,
+	Core,
+	Uncore,
+	Dram,
+	/// `psys`, the whole platform
+	Platform,
+}
+
+impl PowercapDomain {
+	/// Classifies a zone by the last part of its name, so `package-0/core` is a core zone.
+	fn from_zone_name(name: &str) -> Option<Self> {
+		let name = name.rsplit('/').next().unwrap_or(name);
+		match name {
+			"core" => Some(Self::Core),
+			"uncore" => Some(Self::Uncore),
+			"dram" => Some(Self::Dram),
+			"psys" => Some(Self::Platform),
+			_ if name.starts_with("package-") => Some(Self::Package),
+			_ => None,
+		}
+	}
+}
+
+/// A RAPL zone as the kernel exposes it. Powercap always counts in µJ, whatever the energy unit
+/// of the underlying MSR.
+#[derive(Debug, Clone)]
+pub struct RaplDomain {
+	pub energy_path: PathBuf,
+	/// The counter wraps back to 0 after this many µJ
+	pub max_energy_range_uj: u64,
+}
+
+/// Every RAPL zone under powercap by name, whether or not its counter is readable. Subzones are
+/// named after their package, e.g. `package-0`, `package-0/core`, `package-0/dram` or `psys`, so
+/// the names are unique on multi-socket systems too.
+pub fn enumerate_rapl_domains() -> io::Result<BTreeMap<String, RaplDomain>> {
+	let mut zone_names: HashMap<String, String> = HashMap::new();
+	let mut zone_paths = Vec::new();
+	for entry in fs::read_dir(POWERCAP_PATH)? {
+		let path = entry?.path();
+		let Some(zone_id) = path
+			.file_name()
+			.and_then(|name| name.to_str())
+			.filter(|name| name.starts_with(RAPL_ZONE_PREFIX))
+			.map(String::from)
+		else {
+			continue;
+		};
+		if let Ok(name) = fs::read_to_string(path.join("name")) {
+			zone_names.insert(zone_id.clone(), name.trim().to_string());
+			zone_paths.push((zone_id, path));
+		}
+	}
+
+	let mut domains = BTreeMap::new();
+	for (zone_id, path) in zone_paths {
+		let Ok(max_energy_range_uj) = read_u64(&path.join("max_energy_range_uj")) else {
+			continue;
+		};
+		// `intel-rapl:0:1` is a subzone of `intel-rapl:0`
+		let parent_name = zone_id
+			.rsplit_once(':')
+			.filter(|(parent, _)| parent.len() > RAPL_ZONE_PREFIX.len())
+			.and_then(|(parent, _)| zone_names.get(parent));
+		let name = match parent_name {
+			Some(parent_name) => format!("{parent_name}/{}", zone_names[&zone_id]),
+			None => zone_names[&zone_id].clone(),
+		};
+		domains.insert(
+			name,
+			RaplDomain {
+				energy_path: path.join("energy_uj"),
+				max_energy_range_uj,
+			},
+		);
+	}
+	Ok(domains)
+}
+
+#[derive(Debug, Clone)]
+struct PowercapZone {
+	domain: PowercapDomain,
+	energy_path: PathBuf,
+	max_energy_range_uj: u64,
+}
+
+/// RAPL zones of the powercap framework (`/sys/class/powercap/intel-rapl:*`), which newer kernels
+/// also register for AMD. Their `energy_uj` counters cover the same domains as the MSRs without
+/// needing the `msr` module, but are root-only on kernels patched for CVE-2020-8694 unless their
+/// permissions were relaxed.
+#[derive(Debug, Clone)]
+pub struct PowercapZones {
+	zones: Vec<PowercapZone>,
+}
+
+impl PowercapZones {
+	/// Finds the readable RAPL zones of the known domains, skipping any others. Fails
+	/// unless at least one package zone can be read.
+	pub fn discover() -> io::Result<Self> {
+		let zones = enumerate_rapl_domains()?
+			.into_iter()
+			.filter(|(_, domain)| read_u64(&domain.energy_path).is_ok())
+			.filter_map(|(name, domain)| {
+				Some(PowercapZone {
+					domain: PowercapDomain::from_zone_name(&name)?,
+					energy_path: domain.energy_path,
+					max_energy_range_uj: domain.max_energy_range_uj,
+				})
+			})
+			.collect();
+
+		let zones = Self { zones };
+		if !zones.has_domain(PowercapDomain::Package) {
+			return Err(io::Error::new(
+				io::ErrorKind::NotFound,
+				format!("No readable RAPL package zone in {POWERCAP_PATH}"),
+			));
+		}
+		Ok(zones)
+	}
+
+	#[must_use]
+	pub fn has_domain(&self, domain: PowercapDomain) -> bool {
+		self.zones.iter().any(|zone| zone.domain == domain)
+	}
+
+	fn read_energy_uj(&self) -> io::Result<Vec<u64>> {
+		self.zones.iter().map(|zone| read_u64(&zone.energy_path)).collect()
+	}
+
+	/// Measures the power of every domain over `duration`. Powercap has no per-core counters, so
+	/// the core domain is reported as a single core, as with the Intel MSRs.
+	pub fn measure_power_sample(&self, duration: Duration) -> io::Result<PowerSample> {
+		let started_at = Instant::now();
+		let initial_energy = self.read_energy_uj()?;
+		thread::sleep(duration);
+		let final_energy = self.read_energy_uj()?;
+		let interval = started_at.elapsed();
+
+		let power_mw = |domain: PowercapDomain| {
+			let mut energy_uj = None;
+			for ((zone, &start), &end) in self.zones.iter().zip(&initial_energy).zip(&final_energy) {
+				if zone.domain == domain {
+					let delta = if end >= start {
+						end - start
+					} else {
+						zone.max_energy_range_uj - start + end
+					};
+					energy_uj = Some(energy_uj.unwrap_or(0) + delta);
+				}
+			}
+			energy_uj.map(|energy_uj| {
+				let elapsed_s = interval.as_secs_f64();
+				if elapsed_s == 0.0 {
+					0.0
+				} else {
+					energy_uj as f64 / elapsed_s / 1000.0
+				}
+			})
+		};
+
+		let package = power_mw(PowercapDomain::Package).unwrap_or(0.0);
+		let core = power_mw(PowercapDomain::Core);
+		Ok(PowerSample {
+			package,
+			cores: vec![core],
+			cores_estimated: false,
+			pp0: core,
+			pp1: power_mw(PowercapDomain::Uncore),
+			dram: power_mw(PowercapDomain::Dram),
+			platform: power_mw(PowercapDomain::Platform),
+			sockets: HashMap::new(),
+			interval,
+			msr_errors: Vec::new(),
+			quantization_limited: package == 0.0,
+			c0_fraction: None,
+			temperatures: HashMap::new(),
+			frequencies: HashMap::new(),
+			energy_perf_preferences: HashMap::new(),
+			core_types: HashMap::new(),
+			offline_cores: HashSet::new(),
+		})
+	}
+}
+
+fn read_u64(path: &Path) -> io::Result<u64> {
+	fs::read_to_string(path)?.trim().parse().map_err(|e| {
+		io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("Invalid value in {}: {e}", path.display()),
+		)
+	})
+}
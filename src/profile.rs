@@ -0,0 +1,339 @@
+use crate::info::escape_json;
+use crate::perf::{PerfEventAttr, perf_event_open};
+use crate::power::PowerReading;
+use crate::topology::CpuTopology;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// How often each thread of the profiled process has its call stack sampled, in CPU time
+pub const PROFILE_SAMPLE_PERIOD: Duration = Duration::from_millis(10);
+/// Innermost frames of each call stack that power is attributed to
+pub const MAX_PROFILE_FRAMES: usize = 64;
+
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_COUNT_SW_TASK_CLOCK: u64 = 1;
+const PERF_SAMPLE_TID: u64 = 1 << 1;
+const PERF_SAMPLE_CALLCHAIN: u64 = 1 << 5;
+const PERF_SAMPLE_CPU: u64 = 1 << 7;
+/// `exclude_kernel` and `exclude_hv` of the attribute flags, so that a `perf_event_paranoid` of
+/// 2 still allows profiling one's own processes
+const PERF_ATTR_EXCLUDE_KERNEL: u64 = 1 << 5;
+const PERF_ATTR_EXCLUDE_HV: u64 = 1 << 6;
+const PERF_RECORD_SAMPLE: u32 = 9;
+/// Call chain entries from `PERF_CONTEXT_MAX` (-4095) on mark a switch of context, such as
+/// `PERF_CONTEXT_USER`, rather than an address
+const PERF_CONTEXT_MAX: u64 = -4095_i64 as u64;
+/// Data pages of each thread's ring buffer, a power of two. 128 KiB holds well over a second of
+/// samples.
+const RING_BUFFER_PAGES: usize = 32;
+/// Offsets of `data_head` and `data_tail` in `struct perf_event_mmap_page`
+const DATA_HEAD_OFFSET: usize = 1024;
+const DATA_TAIL_OFFSET: usize = 1032;
+
+/// A sampling event of one thread and the ring buffer the kernel writes its samples to.
+struct SampleBuffer {
+	/// Keeps the event open while it is mapped
+	_event: File,
+	map: NonNull<u8>,
+	page_size: usize,
+	/// Bytes of the buffer read so far
+	tail: u64,
+}
+
+impl SampleBuffer {
+	fn open(tid: libc::pid_t) -> io::Result<Self> {
+		let mut attr = PerfEventAttr::new(PERF_TYPE_SOFTWARE, PERF_COUNT_SW_TASK_CLOCK);
+		attr.sample_period = PROFILE_SAMPLE_PERIOD.as_nanos() as u64;
+		attr.sample_type = PERF_SAMPLE_TID | PERF_SAMPLE_CPU | PERF_SAMPLE_CALLCHAIN;
+		attr.flags = PERF_ATTR_EXCLUDE_KERNEL | PERF_ATTR_EXCLUDE_HV;
+		let event = perf_event_open(&attr, tid, -1)?;
+
+		// SAFETY: sysconf has no preconditions
+		let page_size = usize::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).map_err(io::Error::other)?;
+		// SAFETY: maps the event's metadata page and ring buffer, whose length the kernel checks
+		let map = unsafe {
+			libc::mmap(
+				std::ptr::null_mut(),
+				(RING_BUFFER_PAGES + 1) * page_size,
+				libc::PROT_READ | libc::PROT_WRITE,
+				libc::MAP_SHARED,
+				event.as_raw_fd(),
+				0,
+			)
+		};
+		if map == libc::MAP_FAILED {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(Self {
+			_event: event,
+			map: NonNull::new(map.cast()).ok_or_else(|| io::Error::other("perf ring buffer mapped at 0"))?,
+			page_size,
+			tail: 0,
+		})
+	}
+
+	const fn data_size(&self) -> usize {
+		RING_BUFFER_PAGES * self.page_size
+	}
+
+	fn header_field(&self, offset: usize) -> &AtomicU64 {
+		// SAFETY: the field lies in the mapped metadata page, 8-byte aligned, and the kernel only
+		// accesses it atomically
+		unsafe { &*self.map.as_ptr().add(offset).cast::<AtomicU64>() }
+	}
+
+	/// Copies `len` bytes of the ring buffer from the position `at`, joining a record that wraps
+	/// around its end.
+	fn read_bytes(&self, at: u64, len: usize) -> Vec<u8> {
+		let data_size = self.data_size();
+		let start = (at % data_size as u64) as usize;
+		let first = len.min(data_size - start);
+		// SAFETY: the data area follows the metadata page, and both ranges lie within it
+		unsafe {
+			let data = self.map.as_ptr().add(self.page_size);
+			let mut bytes = std::slice::from_raw_parts(data.add(start), first).to_vec();
+			bytes.extend_from_slice(std::slice::from_raw_parts(data, len - first));
+			bytes
+		}
+	}
+
+	/// Passes the CPU and call chain of every sample written since the last drain, leaf first, to
+	/// `on_sample`, and hands the space back to the kernel.
+	fn drain(&mut self, mut on_sample: impl FnMut(usize, &[u64])) {
+		let head = self.header_field(DATA_HEAD_OFFSET).load(Ordering::Acquire);
+		while self.tail + 8 <= head {
+			let header = self.read_bytes(self.tail, 8);
+			let kind = u32::from_ne_bytes(header[0..4].try_into().unwrap_or_default());
+			let size = u16::from_ne_bytes(header[6..8].try_into().unwrap_or_default()) as usize;
+			if size < 8 {
+				// A corrupt header, so skip everything written so far
+				self.tail = head;
+				break;
+			}
+			if kind == PERF_RECORD_SAMPLE {
+				let record = self.read_bytes(self.tail + 8, size - 8);
+				let words: Vec<u64> = record
+					.chunks_exact(8)
+					.map(|word| u64::from_ne_bytes(word.try_into().unwrap_or_default()))
+					.collect();
+				// pid and tid, cpu and reserved, then the call chain length and its entries
+				if let [_, cpu_and_res, nr, chain @ ..] = words.as_slice() {
+					let cpu = (cpu_and_res & u64::from(u32::MAX)) as usize;
+					on_sample(cpu, &chain[..(*nr as usize).min(chain.len())]);
+				}
+			}
+			self.tail += size as u64;
+		}
+		self.header_field(DATA_TAIL_OFFSET).store(self.tail, Ordering::Release);
+	}
+}
+
+impl Drop for SampleBuffer {
+	fn drop(&mut self) {
+		// SAFETY: unmaps the mapping made in `open`, which nothing refers to any more
+		unsafe {
+			libc::munmap(self.map.as_ptr().cast(), (RING_BUFFER_PAGES + 1) * self.page_size);
+		}
+	}
+}
+
+/// An executable mapping of the profiled process, from `/proc/<pid>/maps`.
+struct Mapping {
+	start: u64,
+	end: u64,
+	offset: u64,
+	object: String,
+}
+
+fn read_mappings(pid: libc::pid_t) -> Vec<Mapping> {
+	let Ok(maps) = fs::read_to_string(format!("/proc/{pid}/maps")) else {
+		return Vec::new();
+	};
+	maps.lines()
+		.filter_map(|line| {
+			let mut fields = line.split_whitespace();
+			let (start, end) = fields.next()?.split_once('-')?;
+			if !fields.next()?.contains('x') {
+				return None;
+			}
+			let offset = u64::from_str_radix(fields.next()?, 16).ok()?;
+			let object = fields.nth(2).unwrap_or("[anon]");
+			Some(Mapping {
+				start: u64::from_str_radix(start, 16).ok()?,
+				end: u64::from_str_radix(end, 16).ok()?,
+				offset,
+				object: object.rsplit('/').next().unwrap_or(object).to_string(),
+			})
+		})
+		.collect()
+}
+
+/// A frame of the flame graph with the samples it was on-stack in and their summed core power.
+#[derive(Default)]
+struct FlameNode {
+	samples: u64,
+	watts: f64,
+	children: BTreeMap<String, FlameNode>,
+}
+
+impl FlameNode {
+	fn write_json(&self, name: &str, total_samples: u64, out: &mut String) {
+		let total_watts = if total_samples == 0 {
+			0.0
+		} else {
+			self.watts / total_samples as f64
+		};
+		let _ = write!(
+			out,
+			"{{\"name\":{},\"value\":{},\"total_watts\":{total_watts},\"children\":[",
+			escape_json(name),
+			self.samples
+		);
+		for (index, (name, child)) in self.children.iter().enumerate() {
+			if index > 0 {
+				out.push(',');
+			}
+			child.write_json(name, total_samples, out);
+		}
+		out.push_str("]}");
+	}
+}
+
+/// Samples the call stacks of a process with `perf_event_open` and attributes to each the power
+/// of the core it ran on, for an energy flame graph.
+///
+/// Frames are named `object+offset`, e.g. `libc.so.6+0x2a1b0`, to be resolved with `addr2line`,
+/// as there is no symbol table reader here. The kernel unwinds user stacks through frame
+/// pointers, so code built without them shows only its innermost frame. Threads started after
+/// attaching are not sampled.
+pub struct CallStackProfiler {
+	pid: libc::pid_t,
+	buffers: Vec<SampleBuffer>,
+	mappings: Vec<Mapping>,
+	/// Physical core of each logical CPU
+	cpu_to_core: HashMap<usize, usize>,
+	root: FlameNode,
+}
+
+impl CallStackProfiler {
+	/// Starts sampling every thread of `pid`. Needs `CAP_PERFMON` or the right to ptrace the
+	/// process, and a `perf_event_paranoid` of 2 or less.
+	pub fn attach(pid: libc::pid_t, topology: &CpuTopology) -> io::Result<Self> {
+		let tasks = fs::read_dir(format!("/proc/{pid}/task"))
+			.map_err(|e| io::Error::new(e.kind(), format!("Cannot list the threads of process {pid}: {e}")))?;
+		let mut buffers = Vec::new();
+		let mut last_error = None;
+		for tid in tasks.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok()) {
+			match SampleBuffer::open(tid) {
+				Ok(buffer) => buffers.push(buffer),
+				// Threads may exit while attaching
+				Err(e) => last_error = Some(e),
+			}
+		}
+		if buffers.is_empty() {
+			let e = last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::NotFound));
+			return Err(io::Error::new(
+				e.kind(),
+				format!("Cannot sample the call stacks of process {pid}: {e}"),
+			));
+		}
+
+		let cpu_to_core = topology
+			.core_to_threads
+			.iter()
+			.flat_map(|(&core, threads)| threads.iter().map(move |&cpu| (cpu, core)))
+			.collect();
+		Ok(Self {
+			pid,
+			buffers,
+			mappings: read_mappings(pid),
+			cpu_to_core,
+			root: FlameNode::default(),
+		})
+	}
+
+	/// Whether the profiled process is still running.
+	#[must_use]
+	pub fn is_running(&self) -> bool {
+		Path::new(&format!("/proc/{}", self.pid)).exists()
+	}
+
+	#[must_use]
+	pub const fn samples(&self) -> u64 {
+		self.root.samples
+	}
+
+	/// Attributes the call stacks sampled since the last call to the power of their core in
+	/// `reading`. Cores without a reading get an even share of the package power.
+	pub fn record(&mut self, reading: &PowerReading) {
+		let fallback_watts = reading.package / reading.cores.len().max(1) as f64;
+		let mut stacks = Vec::new();
+		for buffer in &mut self.buffers {
+			buffer.drain(|cpu, chain| {
+				let frames: Vec<u64> = chain
+					.iter()
+					.copied()
+					.filter(|&ip| ip < PERF_CONTEXT_MAX)
+					.take(MAX_PROFILE_FRAMES)
+					.collect();
+				stacks.push((cpu, frames));
+			});
+		}
+
+		let mut mappings_reloaded = false;
+		for (cpu, frames) in stacks {
+			let watts = self
+				.cpu_to_core
+				.get(&cpu)
+				.and_then(|&core| reading.cores.get(core).copied().flatten())
+				.unwrap_or(fallback_watts);
+			// Libraries loaded after attaching are missing until the maps are read again
+			if !mappings_reloaded && frames.iter().any(|&ip| self.frame_name(ip).is_none()) {
+				self.mappings = read_mappings(self.pid);
+				mappings_reloaded = true;
+			}
+
+			let names: Vec<String> = frames
+				.iter()
+				.rev()
+				.map(|&ip| self.frame_name(ip).unwrap_or_else(|| format!("{ip:#x}")))
+				.collect();
+			let mut node = &mut self.root;
+			node.samples += 1;
+			node.watts += watts;
+			for name in names {
+				node = node.children.entry(name).or_default();
+				node.samples += 1;
+				node.watts += watts;
+			}
+		}
+	}
+
+	fn frame_name(&self, ip: u64) -> Option<String> {
+		let mapping = self
+			.mappings
+			.iter()
+			.find(|mapping| (mapping.start..mapping.end).contains(&ip))?;
+		Some(format!("{}+{:#x}", mapping.object, ip - mapping.start + mapping.offset))
+	}
+
+	/// Writes the flame graph in the JSON of d3-flame-graph: nested `{"name","value","children"}`
+	/// nodes with the sample count as `value`. Each node also has `total_watts`, the average core
+	/// power over all samples times the fraction of them the frame was on-stack in, so the
+	/// children of a node add up to at most its own.
+	pub fn write_flame_json(&self, path: &Path) -> io::Result<()> {
+		let mut json = String::new();
+		self.root
+			.write_json(&format!("pid {}", self.pid), self.root.samples, &mut json);
+		let mut writer = BufWriter::new(File::create(path)?);
+		writeln!(writer, "{json}")?;
+		writer.flush()
+	}
+}
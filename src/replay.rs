@@ -0,0 +1,39 @@
+use crate::power::PowerReading;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+
+/// Writes every reading received on `rx` to `path` as NDJSON, one reading per line, until the
+/// sender is dropped. The file is truncated first and flushed after each reading, so a recording
+/// cut short is still readable up to its last line.
+pub fn record_to_file(path: &Path, rx: Receiver<PowerReading>) -> io::Result<()> {
+	let mut file = BufWriter::new(File::create(path)?);
+	for reading in rx {
+		serde_json::to_writer(&mut file, &reading)?;
+		writeln!(file)?;
+		file.flush()?;
+	}
+	Ok(())
+}
+
+/// Reads the readings of a recording made by [`record_to_file`]. The whole file is parsed up
+/// front, so a malformed line fails here, with its line number, rather than midway through a
+/// replay. Blank lines are skipped.
+pub fn replay_from_file(path: &Path) -> io::Result<impl Iterator<Item = PowerReading>> {
+	let mut readings = Vec::new();
+	for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+		let line = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		let reading = serde_json::from_str(&line).map_err(|e| {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("Invalid reading on line {} of {}: {e}", index + 1, path.display()),
+			)
+		})?;
+		readings.push(reading);
+	}
+	Ok(readings.into_iter())
+}
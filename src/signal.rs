@@ -0,0 +1,48 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_shutdown(_signal: libc::c_int) {
+	SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn record_resize(_signal: libc::c_int) {
+	RESIZED.store(true, Ordering::SeqCst);
+}
+
+/// Makes SIGINT and SIGTERM set a flag instead of killing the process, so the monitoring loop can
+/// stop at its next sample and print the session summary. Returns the flag, which stays set once
+/// a signal arrived.
+pub fn install_shutdown_handler() -> io::Result<&'static AtomicBool> {
+	for signal in [libc::SIGINT, libc::SIGTERM] {
+		// SAFETY: the handler only stores to an atomic, which is async-signal-safe
+		let previous = unsafe {
+			libc::signal(
+				signal,
+				request_shutdown as extern "C" fn(libc::c_int) as libc::sighandler_t,
+			)
+		};
+		if previous == libc::SIG_ERR {
+			return Err(io::Error::last_os_error());
+		}
+	}
+	Ok(&SHUTDOWN_REQUESTED)
+}
+
+/// Makes SIGWINCH set a flag when the terminal is resized. Swap it back to `false` to handle
+/// each resize once.
+pub fn install_resize_handler() -> io::Result<&'static AtomicBool> {
+	// SAFETY: the handler only stores to an atomic, which is async-signal-safe
+	let previous = unsafe {
+		libc::signal(
+			libc::SIGWINCH,
+			record_resize as extern "C" fn(libc::c_int) as libc::sighandler_t,
+		)
+	};
+	if previous == libc::SIG_ERR {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(&RESIZED)
+}
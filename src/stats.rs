@@ -0,0 +1,165 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Energy-delay products, which weigh energy use against how long a workload took.
+#[derive(Debug, Clone, Copy)]
+pub struct EnergyDelayProduct {
+	/// Energy × duration, in J·s
+	pub edp: f64,
+	/// Energy × duration², in J·s²
+	pub ed2p: f64,
+}
+
+impl EnergyDelayProduct {
+	pub fn new(energy_joules: f64, duration_s: f64) -> Self {
+		Self {
+			edp: energy_joules * duration_s,
+			ed2p: energy_joules * duration_s * duration_s,
+		}
+	}
+}
+
+/// Least-squares fit of `y = slope * x + intercept`, returned as `(slope, intercept)`. The slope is
+/// 0 when all `x` are equal.
+pub fn linear_regression(x: &[f64], y: &[f64]) -> (f64, f64) {
+	let n = x.len().min(y.len());
+	if n == 0 {
+		return (0.0, 0.0);
+	}
+	let mean_x = x[..n].iter().sum::<f64>() / n as f64;
+	let mean_y = y[..n].iter().sum::<f64>() / n as f64;
+
+	let (covariance, variance) = x[..n].iter().zip(&y[..n]).fold((0.0, 0.0), |(cov, var), (&xi, &yi)| {
+		(cov + (xi - mean_x) * (yi - mean_y), var + (xi - mean_x) * (xi - mean_x))
+	});
+	let slope = if variance > 0.0 { covariance / variance } else { 0.0 };
+	(slope, mean_y - slope * mean_x)
+}
+
+/// Pearson correlation coefficient of `x` and `y`, or `None` when either is constant or there
+/// are fewer than two points.
+pub fn pearson_correlation(x: &[f64], y: &[f64]) -> Option<f64> {
+	let n = x.len().min(y.len());
+	if n < 2 {
+		return None;
+	}
+	let mean_x = x[..n].iter().sum::<f64>() / n as f64;
+	let mean_y = y[..n].iter().sum::<f64>() / n as f64;
+
+	let (covariance, var_x, var_y) =
+		x[..n]
+			.iter()
+			.zip(&y[..n])
+			.fold((0.0, 0.0, 0.0), |(cov, var_x, var_y), (&xi, &yi)| {
+				let (dx, dy) = (xi - mean_x, yi - mean_y);
+				(cov + dx * dy, var_x + dx * dx, var_y + dy * dy)
+			});
+	(var_x > 0.0 && var_y > 0.0).then(|| covariance / (var_x * var_y).sqrt())
+}
+
+/// The `p`-th percentile (0 to 100) of `values` by nearest rank, so 100 is the maximum. Sorts a
+/// copy, so meant for summaries rather than every sample. `None` when `values` is empty.
+pub fn percentile(values: impl IntoIterator<Item = f64>, p: f64) -> Option<f64> {
+	let mut sorted: Vec<f64> = values.into_iter().collect();
+	if sorted.is_empty() {
+		return None;
+	}
+	sorted.sort_by(f64::total_cmp);
+	let rank = (p.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+	Some(sorted[rank.saturating_sub(1)])
+}
+
+const SECONDS_PER_HOUR: u64 = 3600;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HourlyStats {
+	pub avg_watts: f64,
+	pub total_kj: f64,
+	pub sample_count: u64,
+}
+
+impl HourlyStats {
+	fn record(&mut self, watts: f64, interval: Duration) {
+		self.sample_count += 1;
+		self.avg_watts += (watts - self.avg_watts) / self.sample_count as f64;
+		self.total_kj += watts * interval.as_secs_f64() / 1000.0;
+	}
+}
+
+#[derive(Default)]
+pub struct HourlyBuckets {
+	pub buckets: [HourlyStats; 24],
+	pub current_hour: u8,
+	current_hour_start: Option<u64>,
+}
+
+impl HourlyBuckets {
+	/// Adds a package power sample to the bucket of the current UTC hour. When the sample starts a
+	/// new hour, the bucket of the hour that just ended is returned as a formatted log line.
+	pub fn record(&mut self, watts: f64, interval: Duration, now: SystemTime) -> Option<String> {
+		let now_secs = now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+		let hour_start = now_secs - now_secs % SECONDS_PER_HOUR;
+
+		let finished = match self.current_hour_start {
+			Some(start) if start != hour_start => {
+				Some(format_hour_line(start, &self.buckets[self.current_hour as usize]))
+			},
+			_ => None,
+		};
+
+		if self.current_hour_start != Some(hour_start) {
+			self.current_hour = ((hour_start % SECONDS_PER_DAY) / SECONDS_PER_HOUR) as u8;
+			self.current_hour_start = Some(hour_start);
+			self.buckets[self.current_hour as usize] = HourlyStats::default();
+		}
+
+		self.buckets[self.current_hour as usize].record(watts, interval);
+		finished
+	}
+}
+
+fn format_hour_line(hour_start: u64, stats: &HourlyStats) -> String {
+	format!(
+		"Hour {}: avg={:.1} W, total={:.1} kJ",
+		format_utc_hour(hour_start),
+		stats.avg_watts,
+		stats.total_kj
+	)
+}
+
+/// Formats a time as an RFC 3339 UTC timestamp with milliseconds, e.g.
+/// `2024-05-01T13:45:10.250Z`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+	let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+	let unix_secs = since_epoch.as_secs();
+	let (year, month, day) = civil_from_days(unix_secs / SECONDS_PER_DAY);
+	let secs_of_day = unix_secs % SECONDS_PER_DAY;
+	format!(
+		"{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+		secs_of_day / SECONDS_PER_HOUR,
+		secs_of_day / 60 % 60,
+		secs_of_day % 60,
+		since_epoch.subsec_millis()
+	)
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DDTHH:00Z`.
+fn format_utc_hour(unix_secs: u64) -> String {
+	let (year, month, day) = civil_from_days(unix_secs / SECONDS_PER_DAY);
+	let hour = (unix_secs % SECONDS_PER_DAY) / SECONDS_PER_HOUR;
+	format!("{year:04}-{month:02}-{day:02}T{hour:02}:00Z")
+}
+
+/// Converts days since the unix epoch to a proleptic Gregorian `(year, month, day)`.
+const fn civil_from_days(days: u64) -> (u64, u64, u64) {
+	let z = days + 719_468;
+	let era = z / 146_097;
+	let doe = z - era * 146_097;
+	let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	(year, month, day)
+}
@@ -0,0 +1,39 @@
+use crate::topology::CpuTopology;
+use crate::{CpuType, IA32_THERM_STATUS_MSR, INTEL_TEMPERATURE_TARGET_MSR, read_msr};
+use std::collections::HashMap;
+use std::io;
+
+const THERM_STATUS_READING_VALID: u64 = 1 << 31;
+const THERM_STATUS_READOUT_SHIFT: u64 = 16;
+const THERM_STATUS_READOUT_MASK: u64 = 0x7F;
+const TJ_MAX_SHIFT: u64 = 16;
+const TJ_MAX_MASK: u64 = 0xFF;
+
+/// Temperature of the core `cpu_id` runs on: TjMax from `MSR_TEMPERATURE_TARGET` minus the digital
+/// readout of `IA32_THERM_STATUS`, which counts degrees below it.
+pub fn read_core_temp_celsius(cpu_id: usize) -> io::Result<u8> {
+	let status = read_msr(IA32_THERM_STATUS_MSR, cpu_id)?;
+	if status & THERM_STATUS_READING_VALID == 0 {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("Thermal readout of CPU {cpu_id} is not valid"),
+		));
+	}
+	let tj_max = (read_msr(INTEL_TEMPERATURE_TARGET_MSR, cpu_id)? >> TJ_MAX_SHIFT) & TJ_MAX_MASK;
+	let readout = (status >> THERM_STATUS_READOUT_SHIFT) & THERM_STATUS_READOUT_MASK;
+	Ok(tj_max.saturating_sub(readout) as u8)
+}
+
+/// Temperature of every Intel core, read through its first thread. Cores whose sensor cannot be
+/// read are left out. AMD has no such MSRs, so its map is empty.
+#[must_use]
+pub fn read_core_temperatures(cpu_type: &CpuType, topology: &CpuTopology) -> HashMap<usize, u8> {
+	if !matches!(cpu_type, CpuType::Intel) {
+		return HashMap::new();
+	}
+	topology
+		.core_to_threads
+		.iter()
+		.filter_map(|(&core, threads)| Some((core, read_core_temp_celsius(*threads.first()?).ok()?)))
+		.collect()
+}
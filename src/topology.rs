@@ -0,0 +1,398 @@
+use crate::online::{parse_cpu_list, read_online_cpus};
+use crate::{CoreType, CpuType, HwpCapabilities, INTEL_HWP_CAPABILITIES_MSR, read_msr};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::{fs, io};
+
+const SYSFS_CPU_PATH: &str = "/sys/devices/system/cpu";
+const CPUID_DEVICE_PATH: &str = "/dev/cpu";
+
+// CPUID extended topology leaves and the level type of their SMT level
+const CPUID_V2_TOPOLOGY_LEAF: u32 = 0x1F;
+const CPUID_TOPOLOGY_LEAF: u32 = 0xB;
+const CPUID_SMT_LEVEL_TYPE: u32 = 1;
+/// CPUID leaf whose EAX bits 31:24 give the core type on hybrid Intel CPUs
+const CPUID_HYBRID_LEAF: u32 = 0x1A;
+const CPUID_CORE_TYPE_ATOM: u32 = 0x20;
+const CPUID_CORE_TYPE_CORE: u32 = 0x40;
+/// CPUs of the kernel's per-core-type PMUs, which only exist on hybrid Intel CPUs
+const HYBRID_PMU_CPUS: &[(&str, CoreType)] = &[
+	("/sys/devices/cpu_core/cpus", CoreType::Performance),
+	("/sys/devices/cpu_atom/cpus", CoreType::Efficiency),
+];
+
+/// Mapping of physical cores to the logical CPUs (threads) that run on them. Cores are numbered
+/// densely in the order of their lowest thread.
+#[derive(Debug, Clone)]
+pub struct CpuTopology {
+	pub core_to_threads: BTreeMap<usize, Vec<usize>>,
+	/// Turbo Boost Max 3.0 favored cores, filled in by `detect_preferred_cores`
+	pub preferred_cores: BTreeSet<usize>,
+	/// AMD Core Complex of each core, filled in by `detect_ccx`
+	pub core_to_ccx: BTreeMap<usize, usize>,
+	/// Cores of each physical package (socket)
+	pub socket_to_cores: BTreeMap<usize, Vec<usize>>,
+	/// Type of each core of a hybrid CPU, filled in by `detect_core_types`
+	pub core_types: BTreeMap<usize, CoreType>,
+}
+
+impl CpuTopology {
+	/// Reads the topology from sysfs, then from the CPUID extended topology leaves, falling back to
+	/// assuming threads are numbered round-robin across the physical cores.
+	#[must_use]
+	pub fn detect() -> Self {
+		Self::from_sysfs()
+			.or_else(|_| Self::from_cpuid())
+			.unwrap_or_else(|_| Self::from_core_count(num_cpus::get(), num_cpus::get_physical()))
+	}
+
+	pub fn from_sysfs() -> io::Result<Self> {
+		let mut cpu_ids: Vec<usize> = fs::read_dir(SYSFS_CPU_PATH)?
+			.filter_map(|entry| entry.ok()?.file_name().to_str()?.strip_prefix("cpu")?.parse().ok())
+			.collect();
+		cpu_ids.sort_unstable();
+
+		// (package, core) pairs in the order their first thread was seen
+		let mut physical_ids: Vec<(usize, usize)> = Vec::new();
+		let mut core_to_threads: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+		// Some kernels keep the topology directory of offline CPUs, so skip those explicitly
+		let online = read_online_cpus().ok();
+
+		for cpu_id in cpu_ids {
+			if online.as_ref().is_some_and(|online| !online.contains(&cpu_id)) {
+				continue;
+			}
+			let Ok(physical_id) = read_physical_id(cpu_id) else {
+				// Offline CPUs usually have no topology directory
+				continue;
+			};
+			let core = physical_ids
+				.iter()
+				.position(|&id| id == physical_id)
+				.unwrap_or_else(|| {
+					physical_ids.push(physical_id);
+					physical_ids.len() - 1
+				});
+			core_to_threads.entry(core).or_default().push(cpu_id);
+		}
+
+		if core_to_threads.is_empty() {
+			return Err(io::Error::new(
+				io::ErrorKind::NotFound,
+				"No CPU topology found in sysfs",
+			));
+		}
+		let mut socket_to_cores: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+		for (core, &(package, _)) in physical_ids.iter().enumerate() {
+			socket_to_cores.entry(package).or_default().push(core);
+		}
+		Ok(Self {
+			core_to_threads,
+			preferred_cores: BTreeSet::new(),
+			core_to_ccx: BTreeMap::new(),
+			core_types: BTreeMap::new(),
+			socket_to_cores,
+		})
+	}
+
+	/// Groups threads by the x2APIC ID bits above the SMT level of CPUID leaf 0x1F, or leaf 0xB on
+	/// CPUs without it. Each CPU is queried through `/dev/cpu/N/cpuid`, which needs the `cpuid`
+	/// module.
+	pub fn from_cpuid() -> io::Result<Self> {
+		let mut cpu_ids: Vec<usize> = fs::read_dir(CPUID_DEVICE_PATH)?
+			.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+			.collect();
+		cpu_ids.sort_unstable();
+
+		let mut core_ids: Vec<u32> = Vec::new();
+		let mut core_to_threads: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+
+		for cpu_id in cpu_ids {
+			let (x2apic_id, smt_shift) = read_cpuid_topology(cpu_id)?;
+			let core_id = x2apic_id >> smt_shift;
+			let core = core_ids.iter().position(|&id| id == core_id).unwrap_or_else(|| {
+				core_ids.push(core_id);
+				core_ids.len() - 1
+			});
+			core_to_threads.entry(core).or_default().push(cpu_id);
+		}
+
+		if core_to_threads.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::NotFound, "No CPUID devices found"));
+		}
+		// The package level is not decoded, so every core is assumed to be on one socket
+		let socket_to_cores = BTreeMap::from([(0, core_to_threads.keys().copied().collect())]);
+		Ok(Self {
+			core_to_threads,
+			preferred_cores: BTreeSet::new(),
+			core_to_ccx: BTreeMap::new(),
+			core_types: BTreeMap::new(),
+			socket_to_cores,
+		})
+	}
+
+	#[must_use]
+	pub fn from_core_count(total_threads: usize, physical_cores: usize) -> Self {
+		let physical_cores = physical_cores.max(1);
+		let mut core_to_threads: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+		for thread_id in 0..total_threads.max(physical_cores) {
+			core_to_threads
+				.entry(thread_id % physical_cores)
+				.or_default()
+				.push(thread_id);
+		}
+		let socket_to_cores = BTreeMap::from([(0, core_to_threads.keys().copied().collect())]);
+		Self {
+			core_to_threads,
+			preferred_cores: BTreeSet::new(),
+			core_to_ccx: BTreeMap::new(),
+			core_types: BTreeMap::new(),
+			socket_to_cores,
+		}
+	}
+
+	/// Re-reads the thread layout from sysfs, e.g. after CPUs were hotplugged, and returns whether
+	/// it changed. Cores are renumbered from the new layout, so on a change the CCXs, preferred
+	/// cores and core types are cleared and have to be detected again.
+	pub fn refresh(&mut self) -> io::Result<bool> {
+		let current = Self::from_sysfs()?;
+		if current.core_to_threads == self.core_to_threads && current.socket_to_cores == self.socket_to_cores {
+			return Ok(false);
+		}
+		*self = current;
+		Ok(true)
+	}
+
+	/// Marks the cores whose HWP highest performance level is above that of the others, as Turbo
+	/// Boost Max 3.0 does for the cores that reach the highest turbo frequencies. Nothing is marked
+	/// when every core reports the same level or HWP is unavailable.
+	pub fn detect_preferred_cores(&mut self, cpu_type: &CpuType) {
+		self.preferred_cores.clear();
+		if !matches!(cpu_type, CpuType::Intel) {
+			return;
+		}
+
+		let max_perf: Vec<(usize, u8)> = self
+			.core_to_threads
+			.iter()
+			.filter_map(|(&core, threads)| {
+				let value = read_msr(INTEL_HWP_CAPABILITIES_MSR, *threads.first()?).ok()?;
+				Some((core, HwpCapabilities::from_msr(value).max_perf))
+			})
+			.collect();
+		let Some(global_max) = max_perf.iter().map(|&(_, perf)| perf).max() else {
+			return;
+		};
+		if max_perf.iter().all(|&(_, perf)| perf == global_max) {
+			return;
+		}
+		self.preferred_cores = max_perf
+			.into_iter()
+			.filter(|&(_, perf)| perf == global_max)
+			.map(|(core, _)| core)
+			.collect();
+	}
+
+	/// Assigns AMD cores to their Core Complex, the cores sharing an L3 cache, from the L3 cache
+	/// ID in sysfs or, on kernels without it, `topology/cluster_id`. CCXs are numbered densely in
+	/// the order of their first core. Nothing is assigned unless every core's CCX is known and
+	/// there is more than one.
+	pub fn detect_ccx(&mut self, cpu_type: &CpuType) {
+		self.core_to_ccx.clear();
+		if !matches!(cpu_type, CpuType::Amd) {
+			return;
+		}
+
+		let mut ccx_ids: Vec<(usize, usize)> = Vec::new();
+		let mut core_to_ccx = BTreeMap::new();
+		for (&core, threads) in &self.core_to_threads {
+			let Some(ccx_id) = threads.first().and_then(|&cpu_id| read_ccx_id(cpu_id).ok()) else {
+				return;
+			};
+			let ccx = ccx_ids.iter().position(|&id| id == ccx_id).unwrap_or_else(|| {
+				ccx_ids.push(ccx_id);
+				ccx_ids.len() - 1
+			});
+			core_to_ccx.insert(core, ccx);
+		}
+		if ccx_ids.len() > 1 {
+			self.core_to_ccx = core_to_ccx;
+		}
+	}
+
+	/// Classifies the cores of a hybrid Intel CPU from the CPU lists of the `cpu_core` and
+	/// `cpu_atom` PMUs or, on kernels without them, CPUID leaf 0x1A. E-cores without an L3 cache
+	/// are the low-power E-cores of the SoC tile. Nothing is classified unless every core's type is
+	/// known and there is more than one.
+	pub fn detect_core_types(&mut self, cpu_type: &CpuType) {
+		self.core_types.clear();
+		if !matches!(cpu_type, CpuType::Intel) {
+			return;
+		}
+
+		let pmu_core_types: HashMap<usize, CoreType> = HYBRID_PMU_CPUS
+			.iter()
+			.filter_map(|&(path, core_type)| {
+				let cpus = parse_cpu_list(&fs::read_to_string(path).ok()?).ok()?;
+				Some(cpus.into_iter().map(move |cpu_id| (cpu_id, core_type)))
+			})
+			.flatten()
+			.collect();
+		let mut core_types = BTreeMap::new();
+		for (&core, threads) in &self.core_to_threads {
+			let Some(&cpu_id) = threads.first() else {
+				return;
+			};
+			let Some(core_type) = pmu_core_types
+				.get(&cpu_id)
+				.copied()
+				.or_else(|| read_cpuid_core_type(cpu_id))
+			else {
+				return;
+			};
+			let has_l3 = Path::new(&format!("{SYSFS_CPU_PATH}/cpu{cpu_id}/cache/index3")).exists();
+			let core_type = match core_type {
+				CoreType::Efficiency if !has_l3 => CoreType::LowPowerEfficiency,
+				core_type => core_type,
+			};
+			core_types.insert(core, core_type);
+		}
+		let mut kinds = core_types.values();
+		if let Some(first) = kinds.next() {
+			if kinds.any(|core_type| core_type != first) {
+				self.core_types = core_types;
+			}
+		}
+	}
+
+	/// Cores of each CCX, empty when CCXs were not detected.
+	#[must_use]
+	pub fn ccx_groups(&self) -> BTreeMap<usize, Vec<usize>> {
+		let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+		for (&core, &ccx) in &self.core_to_ccx {
+			groups.entry(ccx).or_default().push(core);
+		}
+		groups
+	}
+
+	/// The first CPU of every socket as `(socket, cpu)`, where package-scoped MSRs are read. CPU 0
+	/// stands in for a topology without sockets.
+	#[must_use]
+	pub fn package_cpus(&self) -> Vec<(usize, usize)> {
+		let package_cpus: Vec<(usize, usize)> = self
+			.socket_to_cores
+			.iter()
+			.filter_map(|(&socket, cores)| {
+				let cpu = cores.iter().find_map(|core| self.core_to_threads.get(core)?.first())?;
+				Some((socket, *cpu))
+			})
+			.collect();
+		if package_cpus.is_empty() {
+			return vec![(0, 0)];
+		}
+		package_cpus
+	}
+
+	#[must_use]
+	pub fn physical_cores(&self) -> usize {
+		self.core_to_threads.len()
+	}
+
+	/// SMT degree: the largest number of threads on any core.
+	#[must_use]
+	pub fn threads_per_core(&self) -> usize {
+		self.core_to_threads.values().map(Vec::len).max().unwrap_or(0)
+	}
+
+	#[must_use]
+	pub fn min_threads_per_core(&self) -> usize {
+		self.core_to_threads.values().map(Vec::len).min().unwrap_or(0)
+	}
+
+	#[must_use]
+	pub fn is_smt_uniform(&self) -> bool {
+		self.min_threads_per_core() == self.threads_per_core()
+	}
+}
+
+/// Executes CPUID on `cpu_id` and returns `[eax, ebx, ecx, edx]`.
+fn read_cpuid(cpu_id: usize, leaf: u32, subleaf: u32) -> io::Result<[u32; 4]> {
+	let mut buf = [0u8; 16];
+	fs::File::open(format!("{CPUID_DEVICE_PATH}/{cpu_id}/cpuid"))?
+		.read_exact_at(&mut buf, (u64::from(subleaf) << 32) | u64::from(leaf))?;
+	let register = |i: usize| u32::from_le_bytes([buf[i * 4], buf[i * 4 + 1], buf[i * 4 + 2], buf[i * 4 + 3]]);
+	Ok([register(0), register(1), register(2), register(3)])
+}
+
+/// The hybrid core type CPUID reports for `cpu_id`, if any.
+fn read_cpuid_core_type(cpu_id: usize) -> Option<CoreType> {
+	let [max_leaf, ..] = read_cpuid(cpu_id, 0, 0).ok()?;
+	if max_leaf < CPUID_HYBRID_LEAF {
+		return None;
+	}
+	let [eax, ..] = read_cpuid(cpu_id, CPUID_HYBRID_LEAF, 0).ok()?;
+	match eax >> 24 {
+		CPUID_CORE_TYPE_CORE => Some(CoreType::Performance),
+		CPUID_CORE_TYPE_ATOM => Some(CoreType::Efficiency),
+		_ => None,
+	}
+}
+
+/// The x2APIC ID of `cpu_id` and how many of its low bits select the thread within a core.
+fn read_cpuid_topology(cpu_id: usize) -> io::Result<(u32, u32)> {
+	let [max_leaf, ..] = read_cpuid(cpu_id, 0, 0)?;
+	// Leaf 0x1F is only meaningful when its first subleaf reports logical processors
+	let leaf = if max_leaf >= CPUID_V2_TOPOLOGY_LEAF && read_cpuid(cpu_id, CPUID_V2_TOPOLOGY_LEAF, 0)?[1] != 0 {
+		CPUID_V2_TOPOLOGY_LEAF
+	} else if max_leaf >= CPUID_TOPOLOGY_LEAF {
+		CPUID_TOPOLOGY_LEAF
+	} else {
+		return Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"CPUID extended topology leaves are not supported",
+		));
+	};
+
+	let mut smt_shift = 0;
+	let mut x2apic_id = None;
+	for subleaf in 0.. {
+		let [eax, _, ecx, edx] = read_cpuid(cpu_id, leaf, subleaf)?;
+		let level_type = (ecx >> 8) & 0xFF;
+		if level_type == 0 {
+			break;
+		}
+		x2apic_id = Some(edx);
+		if level_type == CPUID_SMT_LEVEL_TYPE {
+			smt_shift = eax & 0x1F;
+		}
+	}
+
+	x2apic_id.map(|id| (id, smt_shift)).ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("CPUID leaf {leaf:#x} reports no topology levels on CPU {cpu_id}"),
+		)
+	})
+}
+
+fn read_sysfs_id(cpu_id: usize, name: &str) -> io::Result<usize> {
+	fs::read_to_string(format!("{SYSFS_CPU_PATH}/cpu{cpu_id}/{name}"))?
+		.trim()
+		.parse()
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn read_physical_id(cpu_id: usize) -> io::Result<(usize, usize)> {
+	Ok((
+		read_sysfs_id(cpu_id, "topology/physical_package_id")?,
+		read_sysfs_id(cpu_id, "topology/core_id")?,
+	))
+}
+
+/// The L3 cache ID identifies the CCX, with the package so IDs of different sockets never clash.
+fn read_ccx_id(cpu_id: usize) -> io::Result<(usize, usize)> {
+	let package = read_sysfs_id(cpu_id, "topology/physical_package_id")?;
+	let ccx = read_sysfs_id(cpu_id, "cache/index3/id").or_else(|_| read_sysfs_id(cpu_id, "topology/cluster_id"))?;
+	Ok((package, ccx))
+}
@@ -0,0 +1,269 @@
+use crate::energy::EnergySource;
+use crate::hwp::epp_name;
+use crate::keyboard::KeyCommand;
+use crate::monitor::{MonitorState, PowerMonitor, SessionSummary};
+use crate::power::PowerReading;
+use crate::signal::install_shutdown_handler;
+use crate::topology::CpuTopology;
+use crate::{CpuType, MonitorConfig, display};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{Block, Gauge, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::Duration;
+use std::{io, thread};
+
+const PACKAGE_HISTORY: usize = 120;
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Samples on a background thread and draws the readings with the dashboard until it is closed or
+/// SIGINT or SIGTERM arrives, then returns the session summary. `configure` sets up the monitor
+/// before it is calibrated, e.g. its averaging mode, and the gauges are scaled to `tdp_watts`.
+pub fn monitor_with_dashboard(
+	cpu_type: &CpuType,
+	config: &MonitorConfig,
+	tdp_watts: Option<f64>,
+	configure: impl FnOnce(&mut PowerMonitor),
+) -> io::Result<SessionSummary> {
+	let source = EnergySource::detect(cpu_type)?;
+	if let Some(note) = source.note() {
+		eprintln!("{note}");
+	}
+	let mut topology = CpuTopology::detect();
+	topology.detect_ccx(cpu_type);
+	topology.detect_core_types(cpu_type);
+	let mut monitor = PowerMonitor::with_config(topology.physical_cores(), config);
+	configure(&mut monitor);
+	monitor.calibrate(cpu_type, &source)?;
+
+	let shutdown = install_shutdown_handler()?;
+	let (sender, receiver) = mpsc::channel();
+	let (command_sender, commands) = mpsc::channel::<KeyCommand>();
+	let sampler = {
+		let (cpu_type, topology) = (*cpu_type, topology.clone());
+		thread::spawn(move || {
+			monitor.monitor_with_callback(&cpu_type, &topology, &source, |monitor, reading| {
+				loop {
+					match commands.try_recv() {
+						Ok(command) => command.apply(monitor)?,
+						Err(TryRecvError::Empty) => break,
+						// The dashboard was closed
+						Err(TryRecvError::Disconnected) => {
+							monitor.stop();
+							break;
+						},
+					}
+				}
+				// Paused callbacks repeat the last reading, which must not be plotted again
+				let measuring = monitor.state() == MonitorState::Measuring;
+				if (measuring && sender.send(reading.clone()).is_err()) || shutdown.load(Ordering::SeqCst) {
+					monitor.stop();
+				}
+				Ok(())
+			})?;
+			Ok::<_, io::Error>(monitor.session_summary())
+		})
+	};
+
+	TuiDisplay::new(tdp_watts).run(receiver, command_sender, &topology)?;
+	sampler
+		.join()
+		.map_err(|_| io::Error::other("Sampling thread panicked"))?
+}
+
+/// Full-screen dashboard of the readings, opt-in through the `tui` feature: a package power
+/// sparkline and a gauge per core, grouped by CCX when CCXs were detected.
+pub struct TuiDisplay {
+	/// Package power the gauges are scaled to, split evenly across the cores
+	tdp_watts: Option<f64>,
+	package_history: VecDeque<f64>,
+	latest: Option<PowerReading>,
+	paused: bool,
+}
+
+impl TuiDisplay {
+	/// Without a TDP the gauges are scaled to the highest package power seen.
+	#[must_use]
+	pub fn new(tdp_watts: Option<f64>) -> Self {
+		Self {
+			tdp_watts: tdp_watts.filter(|&tdp| tdp > 0.0),
+			package_history: VecDeque::with_capacity(PACKAGE_HISTORY),
+			latest: None,
+			paused: false,
+		}
+	}
+
+	/// Draws every reading received on `rx` until `q` or Esc is pressed or the sender is dropped.
+	/// `p` and `c` are sent to the sampling thread on `commands` to pause or resume sampling and
+	/// to reset the statistics. The terminal is redrawn on resize and restored before returning.
+	pub fn run(
+		mut self,
+		rx: Receiver<PowerReading>,
+		commands: Sender<KeyCommand>,
+		topology: &CpuTopology,
+	) -> io::Result<()> {
+		let mut terminal = ratatui::init();
+		let result = self.event_loop(&mut terminal, &rx, &commands, topology);
+		ratatui::restore();
+		result
+	}
+
+	fn event_loop(
+		&mut self,
+		terminal: &mut DefaultTerminal,
+		rx: &Receiver<PowerReading>,
+		commands: &Sender<KeyCommand>,
+		topology: &CpuTopology,
+	) -> io::Result<()> {
+		loop {
+			let mut redraw = false;
+			loop {
+				match rx.try_recv() {
+					Ok(reading) => {
+						self.record(reading);
+						redraw = true;
+					},
+					Err(TryRecvError::Empty) => break,
+					// Sampling stopped, e.g. on SIGTERM: show what arrived before leaving
+					Err(TryRecvError::Disconnected) => {
+						if redraw {
+							terminal.draw(|frame| self.draw(frame, topology))?;
+						}
+						return Ok(());
+					},
+				}
+			}
+
+			if event::poll(EVENT_POLL_INTERVAL)? {
+				match event::read()? {
+					Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+						KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+						// A send only fails once sampling stopped, which the next loop notices
+						KeyCode::Char('p') if commands.send(KeyCommand::TogglePause).is_ok() => {
+							self.paused = !self.paused;
+							redraw = true;
+						},
+						KeyCode::Char('c') if commands.send(KeyCommand::ResetStatistics).is_ok() => {
+							self.package_history.clear();
+							redraw = true;
+						},
+						_ => {},
+					},
+					Event::Resize(..) => redraw = true,
+					_ => {},
+				}
+			}
+
+			if redraw {
+				terminal.draw(|frame| self.draw(frame, topology))?;
+			}
+		}
+	}
+
+	fn record(&mut self, reading: PowerReading) {
+		self.package_history.push_back(reading.package);
+		if self.package_history.len() > PACKAGE_HISTORY {
+			self.package_history.pop_front();
+		}
+		self.latest = Some(reading);
+	}
+
+	fn draw(&self, frame: &mut Frame, topology: &CpuTopology) {
+		let Some(reading) = &self.latest else {
+			return;
+		};
+		let groups = display::core_groups(topology);
+		let [package_area, cores_area] =
+			Layout::vertical([Constraint::Length(6), Constraint::Min(0)]).areas(frame.area());
+
+		// Sparkline bars are integers, so plot tenths of a watt
+		let history: Vec<u64> = self
+			.package_history
+			.iter()
+			.map(|&watts| (watts * 10.0) as u64)
+			.collect();
+		let package_max = self.package_history.iter().copied().fold(0.0, f64::max);
+		let sparkline = Sparkline::default()
+			.block(Block::bordered().title(package_title(reading, self.paused)))
+			.data(&history)
+			.max((self.tdp_watts.unwrap_or(package_max).max(package_max) * 10.0) as u64)
+			.style(Style::default().fg(Color::Yellow));
+		frame.render_widget(sparkline, package_area);
+
+		let core_scale_watts = self.tdp_watts.unwrap_or(package_max) / topology.physical_cores().max(1) as f64;
+		let group_areas = Layout::vertical(
+			groups
+				.iter()
+				.map(|(_, cores)| Constraint::Length(cores.len() as u16 + 2)),
+		)
+		.split(cores_area);
+		for ((ccx, cores), &area) in groups.iter().zip(group_areas.iter()) {
+			let block = Block::bordered().title(ccx.map_or_else(|| String::from("Cores"), |ccx| format!("CCX {ccx}")));
+			let inner = block.inner(area);
+			frame.render_widget(block, area);
+			let rows = Layout::vertical(cores.iter().map(|_| Constraint::Length(1))).split(inner);
+			for (&core, &row) in cores.iter().zip(rows.iter()) {
+				if reading.offline_cores.contains(&core) {
+					frame.render_widget(
+						Gauge::default().ratio(0.0).label(format!("Core {core}: (offline)")),
+						row,
+					);
+					continue;
+				}
+				render_core_gauge(
+					frame,
+					row,
+					core,
+					reading.cores.get(core).copied().flatten(),
+					reading.energy_perf_preferences.get(&core).copied(),
+					core_scale_watts,
+				);
+			}
+		}
+	}
+}
+
+fn package_title(reading: &PowerReading, paused: bool) -> String {
+	let mut title = format!("Package: {:.2} W", reading.package);
+	if let Some(budget) = &reading.energy_budget {
+		title.push_str(&format!(" | {}", display::format_energy_budget(budget)));
+	}
+	if paused {
+		title.push_str(" [PAUSED]");
+	}
+	title
+}
+
+/// The EPP, when HWP is active, follows the power as `EPP 128 (balance_performance)`.
+fn render_core_gauge(
+	frame: &mut Frame,
+	area: Rect,
+	core: usize,
+	watts: Option<f64>,
+	epp: Option<u8>,
+	scale_watts: f64,
+) {
+	let ratio = match watts {
+		Some(watts) if scale_watts > 0.0 => (watts / scale_watts).clamp(0.0, 1.0),
+		_ => 0.0,
+	};
+	let mut label = watts.map_or_else(
+		|| format!("Core {core}: --"),
+		|watts| format!("Core {core}: {watts:.2} W"),
+	);
+	if let Some(epp) = epp {
+		match epp_name(epp) {
+			Some(name) => label.push_str(&format!(" | EPP {epp} ({name})")),
+			None => label.push_str(&format!(" | EPP {epp}")),
+		}
+	}
+	let gauge = Gauge::default()
+		.gauge_style(Style::default().fg(Color::Cyan))
+		.ratio(ratio)
+		.label(label);
+	frame.render_widget(gauge, area);
+}